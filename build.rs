@@ -2,6 +2,8 @@ use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::fmt::Write as _;
+use std::path::Path;
 
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=res/*");
@@ -9,9 +11,41 @@ fn main() -> Result<()> {
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("res/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    let paths_to_copy = vec!["res/"];
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        embed_assets(&out_dir)?;
+    }
+
+    Ok(())
+}
+
+// Writes a table of every file under res/ for resources.rs to include!
+fn embed_assets(out_dir: &str) -> Result<()> {
+    println!("cargo:rerun-if-changed=res");
+
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR")?).join("res");
+    let mut table = String::from("pub static EMBEDDED_ASSETS: &[(&str, &[u8])] = &[\n");
+
+    for path in glob::glob(&format!("{}/**/*", root.display()))? {
+        let path = path?;
+        if !path.is_file() {
+            continue;
+        }
+
+        let name = path.strip_prefix(&root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        println!("cargo:rerun-if-changed={}", path.display());
+        writeln!(table, "    ({:?}, include_bytes!({:?})),", name, path.canonicalize()?)?;
+    }
+
+    table.push_str("];\n");
+    std::fs::write(Path::new(out_dir).join("embedded_assets.rs"), table)?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,357 @@
+/*
+Packed asset archive.
+
+Layout (all integers little endian):
+
+    header: magic "RPAK", version u32, entry count u32, index offset u64
+    data:   entry blobs, back to back
+    index:  per entry -> name length u16, name (utf8), offset u64,
+            stored size u64, size u64, compression u8, sha256 of content [u8; 32]
+
+The hash is always of the uncompressed content so that it can be checked
+after inflating, whatever compression the entry was packed with.
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::*;
+use sha2::{Digest, Sha256};
+
+pub const MAGIC: [u8; 4] = *b"RPAK";
+pub const VERSION: u32 = 1;
+
+const HEADER_SIZE: u64 = 4 + 4 + 4 + 8;
+/// An index entry with an empty name.
+const MIN_INDEX_ENTRY_SIZE: u64 = 2 + 8 + 8 + 8 + 1 + 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl Compression {
+    fn to_u8(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            other => bail!("unknown compression type {}", other),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub offset: u64,
+    pub stored_size: u64,
+    pub size: u64,
+    pub compression: Compression,
+    pub hash: [u8; 32],
+}
+
+pub struct Archive {
+    file: Mutex<File>,
+    entries: HashMap<String, Entry>,
+}
+
+impl Archive {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)
+            .with_context(|| format!("opening archive {}", path.display()))?;
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        ensure!(magic == MAGIC, "{} is not an asset archive", path.display());
+
+        let version = read_u32(&mut file)?;
+        ensure!(version == VERSION, "unsupported archive version {}", version);
+
+        let count = read_u32(&mut file)?;
+        let index_offset = read_u64(&mut file)?;
+
+        // everything is checked against the file before it sizes an allocation
+        let length = file.metadata()?.len();
+        ensure!(
+            (HEADER_SIZE..=length).contains(&index_offset),
+            "{} has its index outside the file", path.display()
+        );
+        ensure!(
+            count as u64 * MIN_INDEX_ENTRY_SIZE <= length - index_offset,
+            "{} claims more entries than its index can hold", path.display()
+        );
+
+        file.seek(SeekFrom::Start(index_offset))?;
+        let mut index = BufReader::new(&mut file);
+        let mut entries = HashMap::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let name_len = read_u16(&mut index)? as usize;
+            let mut name = vec![0u8; name_len];
+            index.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+
+            let offset = read_u64(&mut index)?;
+            let stored_size = read_u64(&mut index)?;
+            let size = read_u64(&mut index)?;
+            let compression = Compression::from_u8(read_u8(&mut index)?)?;
+            let mut hash = [0u8; 32];
+            index.read_exact(&mut hash)?;
+
+            ensure!(
+                offset >= HEADER_SIZE && offset.checked_add(stored_size).is_some_and(|end| end <= index_offset),
+                "{} lies outside the data of the archive", name
+            );
+            ensure!(
+                compression != Compression::None || size == stored_size,
+                "{} has the wrong size in the archive", name
+            );
+
+            entries.insert(name, Entry { offset, stored_size, size, compression, hash });
+        }
+
+        Ok(Self { file: Mutex::new(file), entries })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Reads, inflates and verifies a single entry.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let entry = self.entries.get(name)
+            .ok_or_else(|| anyhow!("{} is not in the archive", name))?;
+
+        let mut stored = vec![0u8; entry.stored_size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut stored)?;
+        }
+
+        let data = match entry.compression {
+            Compression::None => stored,
+            Compression::Deflate => {
+                // stops a byte past the size it should have, rather than inflating whatever it claims
+                let mut data = Vec::new();
+                flate2::read::DeflateDecoder::new(&stored[..]).take(entry.size.saturating_add(1)).read_to_end(&mut data)?;
+                data
+            },
+        };
+
+        ensure!(data.len() as u64 == entry.size, "{} has the wrong size in the archive", name);
+        ensure!(hash(&data) == entry.hash, "{} failed its hash check, the archive may have been modified", name);
+
+        Ok(data)
+    }
+}
+
+pub struct ArchiveWriter<W: Write + Seek> {
+    out: W,
+    entries: Vec<(String, Entry)>,
+    position: u64,
+}
+
+impl ArchiveWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    pub fn new(mut out: W) -> Result<Self> {
+        // header gets rewritten with the real values in finish
+        out.write_all(&[0u8; HEADER_SIZE as usize])?;
+
+        Ok(Self { out, entries: Vec::new(), position: HEADER_SIZE })
+    }
+
+    /// Adds an entry, storing it uncompressed if deflating would not make it smaller.
+    pub fn add(&mut self, name: &str, data: &[u8], compression: Compression) -> Result<&Entry> {
+        ensure!(name.len() <= u16::MAX as usize, "entry name {} is too long", name);
+        ensure!(!self.entries.iter().any(|(n, _)| n == name), "duplicate entry {}", name);
+
+        let (stored, compression) = match compression {
+            Compression::None => (data.to_vec(), Compression::None),
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                let deflated = encoder.finish()?;
+
+                if deflated.len() < data.len() {
+                    (deflated, Compression::Deflate)
+                } else {
+                    (data.to_vec(), Compression::None)
+                }
+            },
+        };
+
+        self.out.write_all(&stored)?;
+
+        let entry = Entry {
+            offset: self.position,
+            stored_size: stored.len() as u64,
+            size: data.len() as u64,
+            compression,
+            hash: hash(data),
+        };
+        self.position += entry.stored_size;
+        self.entries.push((name.to_string(), entry));
+
+        Ok(&self.entries.last().unwrap().1)
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        let index_offset = self.position;
+
+        for (name, entry) in &self.entries {
+            self.out.write_all(&(name.len() as u16).to_le_bytes())?;
+            self.out.write_all(name.as_bytes())?;
+            self.out.write_all(&entry.offset.to_le_bytes())?;
+            self.out.write_all(&entry.stored_size.to_le_bytes())?;
+            self.out.write_all(&entry.size.to_le_bytes())?;
+            self.out.write_all(&[entry.compression.to_u8()])?;
+            self.out.write_all(&entry.hash)?;
+        }
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&MAGIC)?;
+        self.out.write_all(&VERSION.to_le_bytes())?;
+        self.out.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        self.out.write_all(&index_offset.to_le_bytes())?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+pub fn hash(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn read_u8<R: Read>(r: &mut R) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("trying_somin_{}_{}.pak", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn pack(entries: &[(&str, &[u8], Compression)]) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(Cursor::new(Vec::new())).unwrap();
+        for (name, data, compression) in entries {
+            writer.add(name, data, *compression).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "repeats ".repeat(64);
+        let bytes = pack(&[
+            ("plain.txt", b"stored as is", Compression::None),
+            ("dir/deflated.txt", text.as_bytes(), Compression::Deflate),
+            ("empty", b"", Compression::Deflate),
+        ]);
+        let path = write("round_trip", &bytes);
+        let archive = Archive::open(&path).unwrap();
+
+        assert_eq!(archive.read("plain.txt").unwrap(), b"stored as is");
+        assert_eq!(archive.read("dir/deflated.txt").unwrap(), text.as_bytes());
+        assert_eq!(archive.entry("dir/deflated.txt").unwrap().compression, Compression::Deflate);
+        assert_eq!(archive.read("empty").unwrap(), b"");
+        assert!(archive.read("missing").is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let mut bytes = pack(&[("a.txt", b"original content", Compression::None)]);
+        bytes[HEADER_SIZE as usize] ^= 0xff;
+        let path = write("hash_mismatch", &bytes);
+        let archive = Archive::open(&path).unwrap();
+
+        let error = archive.read("a.txt").unwrap_err().to_string();
+        assert!(error.contains("hash check"), "{}", error);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_index() {
+        let bytes = pack(&[("a.txt", b"content", Compression::None)]);
+
+        let mut huge_count = bytes.clone();
+        huge_count[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let path = write("huge_count", &huge_count);
+        assert!(Archive::open(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        let mut past_end = bytes.clone();
+        past_end[12..20].copy_from_slice(&(bytes.len() as u64 + 1).to_le_bytes());
+        let path = write("past_end", &past_end);
+        assert!(Archive::open(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_entry_past_data() {
+        let mut bytes = pack(&[("a.txt", b"content", Compression::None)]);
+        // stored size follows the name length, name and offset in the index
+        let index_offset = u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize;
+        let stored_size = index_offset + 2 + "a.txt".len() + 8;
+        bytes[stored_size..stored_size + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let path = write("entry_past_data", &bytes);
+
+        assert!(Archive::open(&path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
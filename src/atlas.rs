@@ -0,0 +1,372 @@
+/*
+Packs loose images into one or more atlas pages at load time.
+
+Uses a skyline packer: the top edge of everything placed so far is kept as a
+list of horizontal segments and each image goes wherever it would sit lowest.
+Images are placed tallest first, which keeps the skyline fairly flat.
+
+Every image is surrounded by `extrude` pixels copied from its own edges, so
+linear filtering and mips at the border sample the image rather than its
+neighbour, and then `padding` pixels of transparent space.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::*;
+
+use crate::texture;
+
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasRegion {
+    pub page: usize,
+    /// Pixel rectangle of the image itself, not including extrusion
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub uv: texture::UvRect,
+}
+
+pub struct AtlasImages {
+    pub pages: Vec<image::RgbaImage>,
+    pub regions: HashMap<String, AtlasRegion>,
+}
+
+pub struct Atlas {
+    pub pages: Vec<texture::Texture>,
+    pub regions: HashMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.get(name)
+    }
+
+    pub fn page_texture(&self, region: &AtlasRegion) -> &texture::Texture {
+        &self.pages[region.page]
+    }
+}
+
+pub struct AtlasBuilder {
+    page_size: u32,
+    padding: u32,
+    extrude: u32,
+    images: Vec<(String, image::RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(page_size: u32) -> Self {
+        Self {
+            page_size,
+            padding: 1,
+            extrude: 1,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn extrude(mut self, extrude: u32) -> Self {
+        self.extrude = extrude;
+        self
+    }
+
+    pub fn add(&mut self, name: &str, image: image::RgbaImage) -> &mut Self {
+        self.images.push((name.to_string(), image));
+        self
+    }
+
+    /// Packs everything added so far into as many pages as it needs.
+    pub fn build(self) -> Result<AtlasImages> {
+        let border = self.extrude * 2 + self.padding;
+
+        let mut order = (0..self.images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| {
+            let img = &self.images[*i].1;
+            std::cmp::Reverse((img.height(), img.width()))
+        });
+
+        let mut pages: Vec<(Skyline, image::RgbaImage)> = Vec::new();
+        let mut regions = HashMap::new();
+
+        for i in order {
+            let (name, img) = &self.images[i];
+            let (w, h) = (img.width() + border, img.height() + border);
+
+            ensure!(
+                w <= self.page_size && h <= self.page_size,
+                "{} ({}x{}) does not fit in a {} atlas page", name, img.width(), img.height(), self.page_size
+            );
+            ensure!(img.width() > 0 && img.height() > 0, "{} is an empty image", name);
+            ensure!(!regions.contains_key(name), "duplicate atlas entry {}", name);
+
+            let placed = pages.iter_mut()
+                .enumerate()
+                .find_map(|(page, (skyline, _))| skyline.insert(w, h).map(|pos| (page, pos)));
+
+            let (page, (x, y)) = match placed {
+                Some(placed) => placed,
+                None => {
+                    let mut skyline = Skyline::new(self.page_size, self.page_size);
+                    let pos = skyline.insert(w, h).unwrap();
+                    pages.push((skyline, image::RgbaImage::new(self.page_size, self.page_size)));
+                    (pages.len() - 1, pos)
+                },
+            };
+
+            let (x, y) = (x + self.extrude, y + self.extrude);
+            blit_extruded(&mut pages[page].1, img, x, y, self.extrude);
+
+            regions.insert(name.clone(), AtlasRegion {
+                page,
+                x,
+                y,
+                width: img.width(),
+                height: img.height(),
+                uv: texture::UvRect::from_pixels(x, y, img.width(), img.height(), (self.page_size, self.page_size)),
+            });
+        }
+
+        Ok(AtlasImages {
+            pages: pages.into_iter().map(|(_, page)| page).collect(),
+            regions,
+        })
+    }
+}
+
+impl AtlasImages {
+    pub fn upload(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        options: &texture::TextureOptions,
+    ) -> Result<Atlas> {
+        let pages = self.pages.into_iter()
+            .enumerate()
+            .map(|(i, page)| {
+                let img = image::DynamicImage::ImageRgba8(page);
+                texture::Texture::from_image_with_options(device, queue, &img, Some(&format!("{} {}", label, i)), options)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Atlas { pages, regions: self.regions })
+    }
+}
+
+fn blit_extruded(page: &mut image::RgbaImage, img: &image::RgbaImage, x: u32, y: u32, extrude: u32) {
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let e = extrude as i64;
+
+    for dy in -e..h + e {
+        for dx in -e..w + e {
+            let pixel = *img.get_pixel(dx.clamp(0, w - 1) as u32, dy.clamp(0, h - 1) as u32);
+            page.put_pixel((x as i64 + dx) as u32, (y as i64 + dy) as u32, pixel);
+        }
+    }
+}
+
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+struct Skyline {
+    width: u32,
+    height: u32,
+    segments: Vec<Segment>,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            segments: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    /// Finds the lowest (then leftmost) spot for a w by h rectangle and claims it.
+    fn insert(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32, u32)> = None;
+
+        for i in 0..self.segments.len() {
+            if let Some(y) = self.fit(i, w, h) {
+                let x = self.segments[i].x;
+                if best.is_none_or(|(_, bx, by)| (y, x) < (by, bx)) {
+                    best = Some((i, x, y));
+                }
+            }
+        }
+
+        let (i, x, y) = best?;
+        self.place(i, x, y + h, w);
+
+        Some((x, y))
+    }
+
+    // The y a rectangle starting at segment i would rest at, if it fits at all
+    fn fit(&self, i: usize, w: u32, h: u32) -> Option<u32> {
+        let x = self.segments[i].x;
+        if x + w > self.width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = w as i64;
+        for segment in &self.segments[i..] {
+            if remaining <= 0 {
+                break;
+            }
+            y = y.max(segment.y);
+            remaining -= segment.width as i64;
+        }
+
+        (y + h <= self.height).then_some(y)
+    }
+
+    fn place(&mut self, i: usize, x: u32, top: u32, w: u32) {
+        self.segments.insert(i, Segment { x, y: top, width: w });
+
+        // shrink or drop whatever the new segment now covers
+        let end = x + w;
+        while i + 1 < self.segments.len() {
+            let next = &mut self.segments[i + 1];
+            if next.x >= end {
+                break;
+            }
+
+            let overlap = end - next.x;
+            if overlap >= next.width {
+                self.segments.remove(i + 1);
+            } else {
+                next.x += overlap;
+                next.width -= overlap;
+                break;
+            }
+        }
+
+        // merge neighbours at the same height
+        let mut j = 0;
+        while j + 1 < self.segments.len() {
+            if self.segments[j].y == self.segments[j + 1].y {
+                self.segments[j].width += self.segments[j + 1].width;
+                self.segments.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba([value, value, value, 255]))
+    }
+
+    fn overlaps(a: &AtlasRegion, b: &AtlasRegion) -> bool {
+        a.page == b.page
+            && a.x < b.x + b.width && b.x < a.x + a.width
+            && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    #[test]
+    fn skyline_places_lowest_then_leftmost() {
+        let mut skyline = Skyline::new(10, 10);
+
+        assert_eq!(skyline.insert(4, 3), Some((0, 0)));
+        assert_eq!(skyline.insert(4, 5), Some((4, 0)));
+        assert_eq!(skyline.insert(2, 2), Some((8, 0)));
+        // lowest remaining top is the first rectangle's
+        assert_eq!(skyline.insert(3, 1), Some((0, 3)));
+        // wider than the gap at y 2, so it rests on the tallest segment it spans
+        assert_eq!(skyline.insert(6, 1), Some((0, 5)));
+        assert_eq!(skyline.insert(11, 1), None);
+        assert_eq!(skyline.insert(1, 10), None);
+    }
+
+    #[test]
+    fn skyline_merges_level_segments() {
+        let mut skyline = Skyline::new(8, 8);
+        skyline.insert(4, 2);
+        skyline.insert(4, 2);
+
+        assert_eq!(skyline.segments.len(), 1);
+        assert_eq!(skyline.insert(8, 6), Some((0, 2)));
+        assert_eq!(skyline.insert(1, 1), None);
+    }
+
+    #[test]
+    fn build_packs_without_overlap() {
+        let mut builder = AtlasBuilder::new(64).padding(1).extrude(1);
+        for i in 0..20u32 {
+            builder.add(&format!("image {}", i), solid(3 + i % 7, 2 + i % 5, i as u8));
+        }
+        let atlas = builder.build().unwrap();
+
+        let regions = atlas.regions.values().collect::<Vec<_>>();
+        assert_eq!(regions.len(), 20);
+        for (i, a) in regions.iter().enumerate() {
+            // room for the extrusion on every side
+            assert!(a.x >= 1 && a.y >= 1 && a.x + a.width < 64 && a.y + a.height < 64);
+            for b in &regions[i + 1..] {
+                assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn build_extrudes_edges() {
+        let mut img = solid(2, 2, 10);
+        img.put_pixel(1, 1, image::Rgba([200, 0, 0, 255]));
+        let mut builder = AtlasBuilder::new(16).padding(0).extrude(2);
+        builder.add("a", img);
+        let atlas = builder.build().unwrap();
+
+        let region = atlas.regions["a"];
+        assert_eq!((region.x, region.y), (2, 2));
+        let page = &atlas.pages[0];
+        // the bottom right pixel is copied out to the corner of the extrusion
+        assert_eq!(page.get_pixel(5, 5), &image::Rgba([200, 0, 0, 255]));
+        assert_eq!(page.get_pixel(0, 0), &image::Rgba([10, 10, 10, 255]));
+        assert_eq!(page.get_pixel(6, 6), &image::Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn build_opens_new_pages() {
+        let mut builder = AtlasBuilder::new(8).padding(0).extrude(0);
+        for i in 0..5 {
+            builder.add(&i.to_string(), solid(8, 4, 0));
+        }
+        let atlas = builder.build().unwrap();
+
+        assert_eq!(atlas.pages.len(), 3);
+        assert_eq!(atlas.regions["4"].page, 2);
+    }
+
+    #[test]
+    fn build_rejects_bad_images() {
+        let mut builder = AtlasBuilder::new(8);
+        builder.add("wide", solid(0, 4, 0));
+        assert!(builder.build().err().unwrap().to_string().contains("empty"));
+
+        let mut builder = AtlasBuilder::new(8);
+        builder.add("flat", solid(4, 0, 0));
+        assert!(builder.build().is_err());
+
+        let mut builder = AtlasBuilder::new(8).padding(0).extrude(0);
+        builder.add("big", solid(9, 1, 0));
+        assert!(builder.build().err().unwrap().to_string().contains("does not fit"));
+
+        let mut builder = AtlasBuilder::new(8);
+        builder.add("same", solid(1, 1, 0)).add("same", solid(1, 1, 0));
+        assert!(builder.build().err().unwrap().to_string().contains("duplicate"));
+    }
+}
@@ -0,0 +1,75 @@
+/*
+Packs a directory of loose assets into a single archive.
+
+    cargo run --bin pack -- <input dir> <output file> [--store]
+
+Entry names are the paths relative to the input directory with '/' separators,
+so they match what gets passed to resources::load_binary.
+*/
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use trying_somin::archive::{ArchiveWriter, Compression};
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut compression = Compression::Deflate;
+
+    for arg in args {
+        match arg.as_str() {
+            "--store" => compression = Compression::None,
+            "--deflate" => compression = Compression::Deflate,
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        eprintln!("usage: pack <input dir> <output file> [--store | --deflate]");
+        std::process::exit(1);
+    }
+
+    let input = PathBuf::from(&positional[0]);
+    let output = PathBuf::from(&positional[1]);
+
+    let mut files = Vec::new();
+    collect_files(&input, &mut files)?;
+    files.sort();
+
+    let mut writer = ArchiveWriter::create(&output)?;
+    let (mut total, mut stored) = (0, 0);
+
+    for path in &files {
+        let name = path.strip_prefix(&input)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let data = std::fs::read(path)?;
+
+        let entry = writer.add(&name, &data, compression)?;
+        println!("{:>10} -> {:>10}  {}", entry.size, entry.stored_size, name);
+
+        total += entry.size;
+        stored += entry.stored_size;
+    }
+
+    writer.finish()?;
+    println!("packed {} files, {} -> {} bytes into {}", files.len(), total, stored, output.display());
+
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
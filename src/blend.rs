@@ -0,0 +1,169 @@
+/*
+Blend modes a pipeline can be built with.
+
+Put a BlendMode on a UIRenderable or sprite entity to pick how it is drawn.
+Entities without one are Alpha blended. Opaque ones are drawn first, writing
+depth, everything else goes in the transparent pass, sorted back to front by z,
+and consecutive quads sharing a mode are drawn together.
+*/
+
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Straight alpha, what plain png files hold
+    #[default]
+    Alpha,
+    /// Colour already multiplied by alpha, see TextureOptions::premultiply_alpha.
+    /// Avoids the dark fringes straight alpha gets around filtered edges
+    Premultiplied,
+    /// Adds onto what is behind, scaled by alpha. Particles and lights
+    Additive,
+    /// Darkens what is behind, shadows and tints
+    Multiply,
+    /// Lightens what is behind without blowing out like Additive
+    Screen,
+    /// No blending, writes depth
+    Opaque,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 6] = [
+        BlendMode::Alpha,
+        BlendMode::Premultiplied,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Opaque,
+    ];
+
+    /// Multiply and Screen treat the colour as premultiplied, which straight alpha
+    /// texels also are wherever they are fully opaque.
+    pub fn blend_state(self) -> Option<wgpu::BlendState> {
+        use wgpu::{BlendComponent, BlendFactor, BlendOperation};
+
+        // destination alpha is left as is for the modes that only affect colour
+        let keep_alpha = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+
+        match self {
+            BlendMode::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            }),
+            BlendMode::Multiply => Some(wgpu::BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::Dst,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            }),
+            BlendMode::Screen => Some(wgpu::BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::OneMinusSrc,
+                    operation: BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            }),
+            BlendMode::Opaque => None,
+        }
+    }
+
+    pub fn writes_depth(self) -> bool {
+        self == BlendMode::Opaque
+    }
+}
+
+/// Opaque items first front to back, so the depth test skips what they hide, then the rest
+/// back to front so they blend over whatever is behind them. Higher z is in front.
+/// Stable, so items on the same z keep the order they came in.
+pub fn sort_for_drawing<T>(items: &mut [(T, BlendMode)], z: impl Fn(&T) -> f32) {
+    items.sort_by(|a, b| {
+        b.1.writes_depth().cmp(&a.1.writes_depth())
+            .then_with(|| match a.1.writes_depth() {
+                true => z(&b.0).total_cmp(&z(&a.0)),
+                false => z(&a.0).total_cmp(&z(&b.0)),
+            })
+    });
+}
+
+/// Splits quads (already in draw order) into index ranges of consecutive ones sharing a mode,
+/// or anything else that has to change between draws.
+pub fn batches<K: PartialEq>(modes: impl IntoIterator<Item = K>, indices_per_quad: u32) -> Vec<(K, Range<u32>)> {
+    let mut batches: Vec<(K, Range<u32>)> = Vec::new();
+
+    for (i, mode) in modes.into_iter().enumerate() {
+        let end = (i as u32 + 1) * indices_per_quad;
+        match batches.last_mut() {
+            Some((last, range)) if *last == mode => range.end = end,
+            _ => batches.push((mode, end - indices_per_quad..end)),
+        }
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_front_to_back_then_transparent_back_to_front() {
+        let mut items = vec![
+            ("alpha 0.5", BlendMode::Alpha),
+            ("opaque 0.1", BlendMode::Opaque),
+            ("additive -0.5", BlendMode::Additive),
+            ("opaque 0.7", BlendMode::Opaque),
+            ("alpha 0.2", BlendMode::Alpha),
+            ("opaque -0.3", BlendMode::Opaque),
+        ];
+        let z = |name: &&str| name.rsplit(' ').next().unwrap().parse::<f32>().unwrap();
+        sort_for_drawing(&mut items, z);
+
+        let names = items.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, ["opaque 0.7", "opaque 0.1", "opaque -0.3", "additive -0.5", "alpha 0.2", "alpha 0.5"]);
+    }
+
+    #[test]
+    fn sort_keeps_order_on_the_same_z() {
+        let mut items = vec![(("a", 0.0), BlendMode::Alpha), (("b", 0.0), BlendMode::Opaque), (("c", 0.0), BlendMode::Alpha), (("d", 0.0), BlendMode::Opaque)];
+        sort_for_drawing(&mut items, |(_, z)| *z);
+
+        let names = items.iter().map(|((name, _), _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, ["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn batches_split_on_mode_change() {
+        use BlendMode::*;
+        let batches = batches([Opaque, Opaque, Alpha, Alpha, Alpha, Opaque], 6);
+
+        assert_eq!(batches, [(Opaque, 0..12), (Alpha, 12..30), (Opaque, 30..36)]);
+        assert!(super::batches(Vec::<BlendMode>::new(), 6).is_empty());
+    }
+
+    #[test]
+    fn batches_split_on_anything_in_the_key() {
+        // a mode and material pair, like the sprite batches
+        let quads = [(BlendMode::Alpha, None), (BlendMode::Alpha, Some(1)), (BlendMode::Alpha, Some(1)), (BlendMode::Alpha, Some(2)), (BlendMode::Additive, Some(2))];
+        let batches = batches(quads, 4);
+
+        assert_eq!(batches, [
+            ((BlendMode::Alpha, None), 0..4),
+            ((BlendMode::Alpha, Some(1)), 4..12),
+            ((BlendMode::Alpha, Some(2)), 12..16),
+            ((BlendMode::Additive, Some(2)), 16..20),
+        ]);
+    }
+}
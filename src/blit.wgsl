@@ -0,0 +1,13 @@
+// Fullscreen triangle that copies one texture into another, used to downsample mip levels
+
+#include "shader/fullscreen.wgsl"
+
+@group(0)@binding(0)
+var t_source: texture_2d<f32>;
+@group(0)@binding(1)
+var s_source: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_source, s_source, in.tex_coords);
+}
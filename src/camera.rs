@@ -1,4 +1,7 @@
 use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::stats;
 
 
 /*
@@ -33,7 +36,7 @@ impl Camera {
     }
 
     pub fn position(&self) -> [f32; 2] {
-        return [self.x, self.y];
+        [self.x, self.y]
     }
 
     pub fn build_matrix(&self) -> cgmath::Matrix4<f32> {
@@ -46,7 +49,7 @@ impl Camera {
             far:    1.0,
         }.into();
 
-        return OPENGL_TO_WGPU_MATRIX * ortho;
+        OPENGL_TO_WGPU_MATRIX * ortho
     }
 
     pub fn modify_position(&mut self, x: f32, y: f32) {
@@ -59,8 +62,8 @@ impl Camera {
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 #[repr(C)]
@@ -86,4 +89,73 @@ impl CameraUniform {
 
         //println!("{:?}", self);
     }
-}
\ No newline at end of file
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(
+        &wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }
+            ],
+            label: Some("camera_bind_group_layout"),
+        }
+    )
+}
+
+/// Uniform, buffer and bind group for one camera, so extra cameras (e.g. ones
+/// drawing into a render target) can be bound in place of the main one.
+pub struct CameraBinding {
+    pub uniform: CameraUniform,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl CameraBinding {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, camera: &Camera) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_proj(camera);
+
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("camera_bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }
+                ],
+            }
+        );
+
+        Self { uniform, buffer, bind_group }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniform.update_proj(camera);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+        stats::count_upload(std::mem::size_of::<CameraUniform>() as u64);
+    }
+}
@@ -0,0 +1,624 @@
+/*
+Block compressed textures from DDS and KTX2 containers.
+
+These go to the GPU as they are when the adapter supports BC formats, which
+keeps them at a quarter (BC1, BC4) or half the size of RGBA8. Otherwise the
+top level is decompressed on the CPU and uploaded like any other image.
+*/
+
+use anyhow::*;
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const KTX2_MAGIC: &[u8] = &[0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BcFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc4Snorm,
+    Bc5,
+    Bc5Snorm,
+    Bc7,
+}
+
+impl BcFormat {
+    pub fn block_size(self) -> u32 {
+        match self {
+            BcFormat::Bc1 | BcFormat::Bc4 | BcFormat::Bc4Snorm => 8,
+            _ => 16,
+        }
+    }
+
+    pub fn wgpu_format(self, srgb: bool) -> wgpu::TextureFormat {
+        use wgpu::TextureFormat as F;
+        match (self, srgb) {
+            (BcFormat::Bc1, false) => F::Bc1RgbaUnorm,
+            (BcFormat::Bc1, true) => F::Bc1RgbaUnormSrgb,
+            (BcFormat::Bc2, false) => F::Bc2RgbaUnorm,
+            (BcFormat::Bc2, true) => F::Bc2RgbaUnormSrgb,
+            (BcFormat::Bc3, false) => F::Bc3RgbaUnorm,
+            (BcFormat::Bc3, true) => F::Bc3RgbaUnormSrgb,
+            (BcFormat::Bc4, _) => F::Bc4RUnorm,
+            (BcFormat::Bc4Snorm, _) => F::Bc4RSnorm,
+            (BcFormat::Bc5, _) => F::Bc5RgUnorm,
+            (BcFormat::Bc5Snorm, _) => F::Bc5RgSnorm,
+            (BcFormat::Bc7, false) => F::Bc7RgbaUnorm,
+            (BcFormat::Bc7, true) => F::Bc7RgbaUnormSrgb,
+        }
+    }
+
+    /// Bytes taken by one mip level of the given size.
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        (width.div_ceil(4) * height.div_ceil(4) * self.block_size()) as usize
+    }
+}
+
+pub struct CompressedImage {
+    pub format: BcFormat,
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels, largest first
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Whether these bytes look like a DDS or KTX2 file rather than something for the image crate.
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(DDS_MAGIC) || bytes.starts_with(KTX2_MAGIC)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(DDS_MAGIC) {
+            Self::parse_dds(bytes)
+        } else if bytes.starts_with(KTX2_MAGIC) {
+            Self::parse_ktx2(bytes)
+        } else {
+            bail!("not a DDS or KTX2 file")
+        }
+    }
+
+    fn parse_dds(bytes: &[u8]) -> Result<Self> {
+        use ddsfile::{D3DFormat, DxgiFormat};
+
+        let dds = ddsfile::Dds::read(bytes)?;
+
+        let (format, srgb) = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(DxgiFormat::BC1_UNorm), _) | (_, Some(D3DFormat::DXT1)) => (BcFormat::Bc1, false),
+            (Some(DxgiFormat::BC1_UNorm_sRGB), _) => (BcFormat::Bc1, true),
+            (Some(DxgiFormat::BC2_UNorm), _) | (_, Some(D3DFormat::DXT3)) => (BcFormat::Bc2, false),
+            (Some(DxgiFormat::BC2_UNorm_sRGB), _) => (BcFormat::Bc2, true),
+            (Some(DxgiFormat::BC3_UNorm), _) | (_, Some(D3DFormat::DXT5)) => (BcFormat::Bc3, false),
+            (Some(DxgiFormat::BC3_UNorm_sRGB), _) => (BcFormat::Bc3, true),
+            (Some(DxgiFormat::BC4_UNorm), _) => (BcFormat::Bc4, false),
+            (Some(DxgiFormat::BC4_SNorm), _) => (BcFormat::Bc4Snorm, false),
+            (Some(DxgiFormat::BC5_UNorm), _) => (BcFormat::Bc5, false),
+            (Some(DxgiFormat::BC5_SNorm), _) => (BcFormat::Bc5Snorm, false),
+            (Some(DxgiFormat::BC7_UNorm), _) => (BcFormat::Bc7, false),
+            (Some(DxgiFormat::BC7_UNorm_sRGB), _) => (BcFormat::Bc7, true),
+            (dxgi, d3d) => bail!("unsupported DDS format {:?} / {:?}", dxgi, d3d),
+        };
+
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let data = dds.get_data(0)?;
+
+        let mut levels = Vec::new();
+        let mut offset = 0;
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = format.level_size((width >> level).max(1), (height >> level).max(1));
+            ensure!(offset + size <= data.len(), "DDS file is missing mip level {}", level);
+            levels.push(data[offset..offset + size].to_vec());
+            offset += size;
+        }
+
+        Ok(Self { format, srgb, width, height, levels })
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> Result<Self> {
+        use ktx2::Format as K;
+
+        let reader = ktx2::Reader::new(bytes)?;
+        let header = reader.header();
+
+        ensure!(
+            header.supercompression_scheme.is_none(),
+            "supercompressed KTX2 files ({:?}) are not supported", header.supercompression_scheme
+        );
+        ensure!(header.layer_count <= 1 && header.face_count == 1, "KTX2 arrays and cubemaps are not supported");
+
+        let (format, srgb) = match header.format {
+            Some(K::BC1_RGB_UNORM_BLOCK) | Some(K::BC1_RGBA_UNORM_BLOCK) => (BcFormat::Bc1, false),
+            Some(K::BC1_RGB_SRGB_BLOCK) | Some(K::BC1_RGBA_SRGB_BLOCK) => (BcFormat::Bc1, true),
+            Some(K::BC2_UNORM_BLOCK) => (BcFormat::Bc2, false),
+            Some(K::BC2_SRGB_BLOCK) => (BcFormat::Bc2, true),
+            Some(K::BC3_UNORM_BLOCK) => (BcFormat::Bc3, false),
+            Some(K::BC3_SRGB_BLOCK) => (BcFormat::Bc3, true),
+            Some(K::BC4_UNORM_BLOCK) => (BcFormat::Bc4, false),
+            Some(K::BC4_SNORM_BLOCK) => (BcFormat::Bc4Snorm, false),
+            Some(K::BC5_UNORM_BLOCK) => (BcFormat::Bc5, false),
+            Some(K::BC5_SNORM_BLOCK) => (BcFormat::Bc5Snorm, false),
+            Some(K::BC7_UNORM_BLOCK) => (BcFormat::Bc7, false),
+            Some(K::BC7_SRGB_BLOCK) => (BcFormat::Bc7, true),
+            other => bail!("unsupported KTX2 format {:?}", other),
+        };
+
+        let levels = reader.levels().map(|level| level.to_vec()).collect();
+
+        Ok(Self { format, srgb, width: header.pixel_width, height: header.pixel_height, levels })
+    }
+
+    /// Whether the whole mip chain can be uploaded as it is, wgpu wants the
+    /// top level to be a whole number of blocks.
+    pub fn can_upload(&self, device: &wgpu::Device) -> bool {
+        device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC) && self.is_block_aligned()
+    }
+
+    pub fn is_block_aligned(&self) -> bool {
+        self.width.is_multiple_of(4) && self.height.is_multiple_of(4)
+    }
+
+    /// Decodes the top level to RGBA8.
+    pub fn decompress(&self) -> Result<image::RgbaImage> {
+        let data = &self.levels[0];
+        let block_size = self.format.block_size() as usize;
+        ensure!(data.len() >= self.format.level_size(self.width, self.height), "compressed data is truncated");
+
+        let mut img = image::RgbaImage::new(self.width, self.height);
+        let blocks_wide = self.width.div_ceil(4);
+
+        for (i, block) in data.chunks_exact(block_size).enumerate() {
+            let bx = (i as u32 % blocks_wide) * 4;
+            let by = (i as u32 / blocks_wide) * 4;
+            if by >= self.height {
+                break;
+            }
+
+            let texels = match self.format {
+                BcFormat::Bc1 => decode_color(block, true),
+                BcFormat::Bc2 => {
+                    let mut texels = decode_color(&block[8..], false);
+                    for (j, texel) in texels.iter_mut().enumerate() {
+                        let nibble = (block[j / 2] >> ((j % 2) * 4)) & 0xF;
+                        texel[3] = nibble * 17;
+                    }
+                    texels
+                },
+                BcFormat::Bc3 => {
+                    let mut texels = decode_color(&block[8..], false);
+                    for (texel, alpha) in texels.iter_mut().zip(decode_channel(&block[..8], false)) {
+                        texel[3] = alpha;
+                    }
+                    texels
+                },
+                BcFormat::Bc4 | BcFormat::Bc4Snorm => {
+                    let red = decode_channel(block, self.format == BcFormat::Bc4Snorm);
+                    red.map(|r| [r, r, r, 255])
+                },
+                BcFormat::Bc5 | BcFormat::Bc5Snorm => {
+                    let snorm = self.format == BcFormat::Bc5Snorm;
+                    let red = decode_channel(&block[..8], snorm);
+                    let green = decode_channel(&block[8..], snorm);
+                    let mut texels = [[0, 0, 0, 255]; 16];
+                    for j in 0..16 {
+                        texels[j][0] = red[j];
+                        texels[j][1] = green[j];
+                    }
+                    texels
+                },
+                BcFormat::Bc7 => decode_bc7(block),
+            };
+
+            for (j, texel) in texels.iter().enumerate() {
+                let (x, y) = (bx + j as u32 % 4, by + j as u32 / 4);
+                if x < self.width && y < self.height {
+                    img.put_pixel(x, y, image::Rgba(*texel));
+                }
+            }
+        }
+
+        Ok(img)
+    }
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1F) as u32;
+    let g = ((color >> 5) & 0x3F) as u32;
+    let b = (color & 0x1F) as u32;
+    [(r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8]
+}
+
+// BC1 style colour block, allow_alpha is only true for actual BC1 where c0 <= c1 selects the 3 colour mode
+fn decode_color(block: &[u8], allow_alpha: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32, d: u32| -> [u8; 4] {
+        let channel = |i: usize| ((a[i] as u32 * wa + b[i] as u32 * wb) / d) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+
+    let palette = if c0 > c1 || !allow_alpha {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(1, 1, 2), [0, 0, 0, 0]]
+    };
+
+    let mut texels = [[0; 4]; 16];
+    for (j, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (j * 2)) & 0b11) as usize];
+    }
+    texels
+}
+
+// BC4 style single channel block, also the alpha half of BC3
+fn decode_channel(block: &[u8], snorm: bool) -> [u8; 16] {
+    let (e0, e1) = if snorm {
+        ((block[0] as i8).max(-127) as i32, (block[1] as i8).max(-127) as i32)
+    } else {
+        (block[0] as i32, block[1] as i32)
+    };
+
+    let mut palette = [0i32; 8];
+    palette[0] = e0;
+    palette[1] = e1;
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * e0 + i as i32 * e1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * e0 + i as i32 * e1) / 5;
+        }
+        palette[6] = if snorm { -127 } else { 0 };
+        palette[7] = if snorm { 127 } else { 255 };
+    }
+
+    let mut bits = 0u64;
+    for (i, byte) in block[2..8].iter().enumerate() {
+        bits |= (*byte as u64) << (i * 8);
+    }
+
+    let mut values = [0u8; 16];
+    for (j, value) in values.iter_mut().enumerate() {
+        let v = palette[((bits >> (j * 3)) & 0b111) as usize];
+        // signed values are shifted into 0..255 so they can sit in an RGBA8 image
+        *value = if snorm { (v + 128).clamp(0, 255) as u8 } else { v as u8 };
+    }
+    values
+}
+
+// BC7 modes, in the order of the spec's table
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p bit per endpoint, or one shared by both endpoints of a subset.
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Modes 4 and 5 have a second set of indices, for alpha or colour.
+    index_bits_2: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode {
+        subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits_2: 0,
+    },
+    Bc7Mode {
+        subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits_2: 0,
+    },
+    Bc7Mode {
+        subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 0,
+    },
+    Bc7Mode {
+        subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits_2: 0,
+    },
+    Bc7Mode {
+        subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1,
+        color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 3,
+    },
+    Bc7Mode {
+        subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0,
+        color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 2,
+    },
+    Bc7Mode {
+        subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits_2: 0,
+    },
+    Bc7Mode {
+        subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0,
+        color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits_2: 0,
+    },
+];
+
+// which subset each texel is in, one bit per texel for two subsets and two bits for three
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80,
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a,
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c,
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+// the texel whose index has its top bit left out, for the second subset (and third)
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const BC7_ANCHORS_3: [[u8; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        2 => ((BC7_PARTITIONS_2[partition] >> texel) & 1) as usize,
+        3 => ((BC7_PARTITIONS_3[partition] >> (texel * 2)) & 3) as usize,
+        _ => 0,
+    }
+}
+
+fn bc7_is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0 || match subsets {
+        2 => texel == BC7_ANCHORS_2[partition] as usize,
+        3 => BC7_ANCHORS_3[partition].contains(&(texel as u8)),
+        _ => false,
+    }
+}
+
+fn bc7_interpolate(e0: u8, e1: u8, index: u32, bits: u32) -> u8 {
+    let weight = match bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    };
+    (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+}
+
+// BC7 block, fields are packed from the lowest bit up starting with the mode
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let bits = u128::from_le_bytes(block[..16].try_into().unwrap());
+    let mut position = 0;
+    let mut read = |count: u32| -> u32 {
+        let value = ((bits >> position) & ((1u128 << count) - 1)) as u32;
+        position += count;
+        value
+    };
+
+    // the mode is the number of zeros before the first set bit, all zeros is reserved
+    let mode = block[0].trailing_zeros();
+    if mode >= 8 {
+        return [[0; 4]; 16];
+    }
+    read(mode + 1);
+    let m = &BC7_MODES[mode as usize];
+
+    let partition = read(m.partition_bits) as usize;
+    let rotation = read(m.rotation_bits);
+    let index_selection = read(m.index_selection_bits);
+
+    // each channel of every endpoint, then the alpha, then the p bits
+    let endpoints = m.subsets * 2;
+    let mut colors = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for color in colors.iter_mut().take(endpoints) {
+            color[channel] = read(m.color_bits);
+        }
+    }
+    for color in colors.iter_mut().take(endpoints) {
+        color[3] = if m.alpha_bits > 0 { read(m.alpha_bits) } else { 255 };
+    }
+
+    let mut pbits = [None; 6];
+    if m.endpoint_pbits {
+        for pbit in pbits.iter_mut().take(endpoints) {
+            *pbit = Some(read(1));
+        }
+    } else if m.shared_pbits {
+        for subset in 0..m.subsets {
+            let pbit = read(1);
+            pbits[subset * 2] = Some(pbit);
+            pbits[subset * 2 + 1] = Some(pbit);
+        }
+    }
+
+    // back to 8 bits, repeating the top bits in the ones that were cut off
+    let mut endpoint_colors = [[0u8; 4]; 6];
+    for e in 0..endpoints {
+        for channel in 0..4 {
+            let mut value = colors[e][channel];
+            let mut size = if channel < 3 { m.color_bits } else { m.alpha_bits };
+            if size == 0 {
+                endpoint_colors[e][channel] = 255;
+                continue;
+            }
+            if let Some(pbit) = pbits[e] {
+                value = (value << 1) | pbit;
+                size += 1;
+            }
+            endpoint_colors[e][channel] = ((value << (8 - size)) | (value >> (2 * size - 8))) as u8;
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = read(m.index_bits - bc7_is_anchor(m.subsets, partition, texel) as u32);
+    }
+    let mut indices_2 = [0u32; 16];
+    if m.index_bits_2 > 0 {
+        for (texel, index) in indices_2.iter_mut().enumerate() {
+            *index = read(m.index_bits_2 - (texel == 0) as u32);
+        }
+    }
+
+    let mut texels = [[0u8; 4]; 16];
+    for (texel, out) in texels.iter_mut().enumerate() {
+        let subset = bc7_subset(m.subsets, partition, texel);
+        let (e0, e1) = (endpoint_colors[subset * 2], endpoint_colors[subset * 2 + 1]);
+
+        let (color, alpha) = match (m.index_bits_2, index_selection) {
+            (0, _) => ((indices[texel], m.index_bits), (indices[texel], m.index_bits)),
+            (_, 0) => ((indices[texel], m.index_bits), (indices_2[texel], m.index_bits_2)),
+            _ => ((indices_2[texel], m.index_bits_2), (indices[texel], m.index_bits)),
+        };
+        for channel in 0..3 {
+            out[channel] = bc7_interpolate(e0[channel], e1[channel], color.0, color.1);
+        }
+        out[3] = bc7_interpolate(e0[3], e1[3], alpha.0, alpha.1);
+
+        if rotation > 0 {
+            out.swap(3, rotation as usize - 1);
+        }
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // packs fields from the lowest bit up, like the blocks are read
+    struct BlockWriter {
+        bits: u128,
+        position: u32,
+    }
+
+    impl BlockWriter {
+        fn new() -> Self {
+            Self { bits: 0, position: 0 }
+        }
+
+        fn write(&mut self, count: u32, value: u32) -> &mut Self {
+            assert!(count == 32 || value < 1 << count);
+            self.bits |= (value as u128) << self.position;
+            self.position += count;
+            self
+        }
+
+        fn finish(&self) -> [u8; 16] {
+            assert_eq!(self.position, 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    #[test]
+    fn bc7_anchors_are_in_their_subsets() {
+        for partition in 0..64 {
+            assert_eq!(bc7_subset(2, partition, BC7_ANCHORS_2[partition] as usize), 1, "partition {}", partition);
+            for (subset, anchor) in BC7_ANCHORS_3[partition].iter().enumerate() {
+                assert_eq!(bc7_subset(3, partition, *anchor as usize), subset + 1, "partition {}", partition);
+            }
+        }
+    }
+
+    #[test]
+    fn bc7_mode_6() {
+        let mut block = BlockWriter::new();
+        block.write(7, 1 << 6);
+        // red, green, blue and alpha of both endpoints: black and opaque to white and half transparent
+        for (e0, e1) in [(0, 127), (0, 127), (0, 127), (127, 63)] {
+            block.write(7, e0).write(7, e1);
+        }
+        block.write(1, 0).write(1, 1);
+        // the anchor's index has three bits, every other texel four
+        block.write(3, 0);
+        for texel in 1..16 {
+            block.write(4, texel);
+        }
+        let texels = decode_bc7(&block.finish());
+
+        assert_eq!(texels[0], [0, 0, 0, 254]);
+        assert_eq!(texels[15], [255, 255, 255, 127]);
+        let middle = bc7_interpolate(0, 255, 8, 4);
+        assert_eq!(texels[8], [middle, middle, middle, bc7_interpolate(254, 127, 8, 4)]);
+    }
+
+    #[test]
+    fn bc7_mode_5_rotation_and_separate_alpha() {
+        let mut block = BlockWriter::new();
+        block.write(6, 1 << 5);
+        // rotation 1 swaps red and alpha once decoded
+        block.write(2, 1);
+        for (e0, e1) in [(127, 0), (0, 127), (0, 0)] {
+            block.write(7, e0).write(7, e1);
+        }
+        block.write(8, 255).write(8, 0);
+        block.write(1, 0);
+        for _ in 1..16 {
+            block.write(2, 3);
+        }
+        block.write(1, 1);
+        for _ in 1..16 {
+            block.write(2, 0);
+        }
+        let texels = decode_bc7(&block.finish());
+
+        // texel 0: colour from the first endpoint, alpha index 1 of the way to transparent
+        let alpha = bc7_interpolate(255, 0, 1, 2);
+        assert_eq!(texels[0], [alpha, 0, 0, 255]);
+        assert_eq!(texels[5], [255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn bc7_two_subsets() {
+        let mut block = BlockWriter::new();
+        // mode 1, partition 13: the top two rows are the first subset, the bottom two the second
+        block.write(2, 0b10).write(6, 13);
+        for (e0, e1, e2, e3) in [(63, 63, 0, 0), (0, 0, 63, 63), (0, 0, 0, 0)] {
+            block.write(6, e0).write(6, e1).write(6, e2).write(6, e3);
+        }
+        block.write(1, 1).write(1, 1);
+        for _ in 0..46 {
+            block.write(1, 0);
+        }
+        let texels = decode_bc7(&block.finish());
+
+        assert_eq!(texels[0], [255, 2, 2, 255]);
+        assert_eq!(texels[7], [255, 2, 2, 255]);
+        assert_eq!(texels[8], [2, 255, 2, 255]);
+        assert_eq!(texels[15], [2, 255, 2, 255]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode() {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+}
@@ -0,0 +1,42 @@
+// Debug shapes and text, see debug_draw.rs. Everything samples the ui font,
+// shapes a solid part of it, and the vertex colour is masked by its alpha.
+
+#include "shader/camera.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+#ifdef SCREEN_SPACE
+    out.clip_position = screen_to_clip(in.position);
+#else
+    out.clip_position = world_to_clip(in.position);
+#endif
+    out.tex_coords = in.tex_coords;
+    out.color = in.color;
+
+    return out;
+}
+
+@group(0)@binding(0)
+var t_sheet: texture_2d<f32>;
+@group(0)@binding(1)
+var t_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_sheet, t_sampler, in.tex_coords).a;
+
+    return vec4(in.color.rgb, in.color.a * coverage);
+}
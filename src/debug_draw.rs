@@ -0,0 +1,436 @@
+/*
+Immediate mode debug drawing.
+
+Call the shape functions on State::debug from anywhere in update, each returns
+the shape so it can be changed before it is drawn:
+
+    self.debug.rect([0.0, 0.0], [32.0, 32.0], debug_draw::RED).seconds(2.0);
+    self.debug.text([8.0, 20.0], "paused", 12.0, debug_draw::WHITE).screen();
+
+Shapes are in world space unless made screen(), where positions are pixels
+from the bottom left corner. They last for the frame they were added in
+unless given seconds(). Everything, text included, is turned into coloured
+triangles in one vertex buffer per space, drawn in front of the scene with a
+single pipeline. Shapes sample a solid part of the ui font so text can share it.
+*/
+
+use std::time;
+
+use wgpu::util::DeviceExt;
+
+use crate::blend;
+use crate::stats;
+use crate::text;
+
+pub type Color = [f32; 4];
+
+pub const WHITE: Color = [1.0, 1.0, 1.0, 1.0];
+pub const BLACK: Color = [0.0, 0.0, 0.0, 1.0];
+pub const RED: Color = [1.0, 0.0, 0.0, 1.0];
+pub const GREEN: Color = [0.0, 1.0, 0.0, 1.0];
+pub const BLUE: Color = [0.0, 0.0, 1.0, 1.0];
+pub const YELLOW: Color = [1.0, 1.0, 0.0, 1.0];
+pub const CYAN: Color = [0.0, 1.0, 1.0, 1.0];
+pub const MAGENTA: Color = [1.0, 0.0, 1.0, 1.0];
+
+/// The one pipeline debug shapes are drawn with.
+pub const BLEND_MODE: blend::BlendMode = blend::BlendMode::Alpha;
+
+/// In front of the scene but behind the overlays at 0.9.
+const Z: f32 = 0.8;
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl DebugVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ]
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Line { from: [f32; 2], to: [f32; 2] },
+    Rect { min: [f32; 2], max: [f32; 2] },
+    Circle { center: [f32; 2], radius: f32 },
+    Arrow { from: [f32; 2], to: [f32; 2] },
+    Grid { origin: [f32; 2], cell: [f32; 2], cells: [u32; 2] },
+    Text { position: [f32; 2], text: String, size: f32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Shape {
+    kind: Kind,
+    color: Color,
+    thickness: f32,
+    filled: bool,
+    screen: bool,
+    // seconds left to draw for, None for the frame it was added in only
+    remaining: Option<f32>,
+}
+
+impl Shape {
+    /// Positions are pixels from the bottom left corner of the window rather than in the world.
+    pub fn screen(&mut self) -> &mut Self {
+        self.screen = true;
+        self
+    }
+
+    /// Keeps drawing the shape for this long rather than the one frame.
+    pub fn seconds(&mut self, seconds: f32) -> &mut Self {
+        self.remaining = Some(seconds);
+        self
+    }
+
+    /// Width of lines and outlines, in the same units as the positions. 1 by default.
+    pub fn thickness(&mut self, thickness: f32) -> &mut Self {
+        self.thickness = thickness;
+        self
+    }
+
+    /// Fills rects and circles in rather than outlining them.
+    pub fn filled(&mut self) -> &mut Self {
+        self.filled = true;
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct DebugDraw {
+    shapes: Vec<Shape>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: [f32; 2], to: [f32; 2], color: Color) -> &mut Shape {
+        self.add(Kind::Line { from, to }, color)
+    }
+
+    pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: Color) -> &mut Shape {
+        self.add(Kind::Rect { min, max }, color)
+    }
+
+    pub fn circle(&mut self, center: [f32; 2], radius: f32, color: Color) -> &mut Shape {
+        self.add(Kind::Circle { center, radius }, color)
+    }
+
+    /// A line with a head at to.
+    pub fn arrow(&mut self, from: [f32; 2], to: [f32; 2], color: Color) -> &mut Shape {
+        self.add(Kind::Arrow { from, to }, color)
+    }
+
+    /// cells[0] by cells[1] cells of the given size, starting at origin.
+    pub fn grid(&mut self, origin: [f32; 2], cell: [f32; 2], cells: [u32; 2], color: Color) -> &mut Shape {
+        self.add(Kind::Grid { origin, cell, cells }, color)
+    }
+
+    /// ASCII text in the ui font with its top left corner at position.
+    pub fn text(&mut self, position: [f32; 2], text: &str, size: f32, color: Color) -> &mut Shape {
+        self.add(Kind::Text { position, text: text.to_string(), size }, color)
+    }
+
+    fn add(&mut self, kind: Kind, color: Color) -> &mut Shape {
+        self.shapes.push(Shape { kind, color, thickness: 1.0, filled: false, screen: false, remaining: None });
+        self.shapes.last_mut().unwrap()
+    }
+
+    /// Drops the shapes that are done, call before anything is drawn for the frame.
+    pub fn frame(&mut self, dt: time::Duration) {
+        let dt = dt.as_secs_f32();
+
+        self.shapes.retain_mut(|shape| match &mut shape.remaining {
+            Some(remaining) => {
+                *remaining -= dt;
+                *remaining > 0.0
+            },
+            None => false,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    /// Triangles for the world space shapes, then for the screen space ones.
+    pub fn tessellate(&self) -> (DebugVecs, DebugVecs) {
+        let mut world = DebugVecs::default();
+        let mut screen = DebugVecs::default();
+
+        for shape in &self.shapes {
+            let vecs = if shape.screen { &mut screen } else { &mut world };
+            vecs.shape(shape);
+        }
+
+        (world, screen)
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct DebugVecs {
+    pub vertices: Vec<DebugVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl DebugVecs {
+    fn shape(&mut self, shape: &Shape) {
+        let color = shape.color;
+        let thickness = shape.thickness;
+
+        match &shape.kind {
+            Kind::Line { from, to } => self.line(*from, *to, thickness, color),
+            Kind::Rect { min, max } if shape.filled => self.rect(*min, *max, color),
+            Kind::Rect { min, max } => {
+                // bands centred on the edges, overlapping at the corners
+                let half = thickness / 2.0;
+                let (left, right) = (min[0] - half, max[0] + half);
+                self.rect([left, max[1] - half], [right, max[1] + half], color);
+                self.rect([left, min[1] - half], [right, min[1] + half], color);
+                self.rect([min[0] - half, min[1] + half], [min[0] + half, max[1] - half], color);
+                self.rect([max[0] - half, min[1] + half], [max[0] + half, max[1] - half], color);
+            },
+            Kind::Circle { center, radius } => {
+                let point = |i: usize| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                    [center[0] + radius * angle.cos(), center[1] + radius * angle.sin()]
+                };
+                for i in 0..CIRCLE_SEGMENTS {
+                    if shape.filled {
+                        self.triangle([*center, point(i), point(i + 1)], color);
+                    } else {
+                        self.line(point(i), point(i + 1), thickness, color);
+                    }
+                }
+            },
+            Kind::Arrow { from, to } => {
+                let (direction, length) = direction(*from, *to);
+                let head = (thickness * 4.0).max(8.0).min(length);
+                let base = [to[0] - direction[0] * head, to[1] - direction[1] * head];
+                let side = [-direction[1] * head * 0.5, direction[0] * head * 0.5];
+
+                self.line(*from, base, thickness, color);
+                self.triangle([*to, [base[0] + side[0], base[1] + side[1]], [base[0] - side[0], base[1] - side[1]]], color);
+            },
+            Kind::Grid { origin, cell, cells } => {
+                let size = [cell[0] * cells[0] as f32, cell[1] * cells[1] as f32];
+                for i in 0..=cells[0] {
+                    let x = origin[0] + cell[0] * i as f32;
+                    self.line([x, origin[1]], [x, origin[1] + size[1]], thickness, color);
+                }
+                for i in 0..=cells[1] {
+                    let y = origin[1] + cell[1] * i as f32;
+                    self.line([origin[0], y], [origin[0] + size[0], y], thickness, color);
+                }
+            },
+            Kind::Text { position, text, size } => self.text([position[0], position[1], Z], text, *size, color),
+        }
+    }
+
+    /// Coloured ascii text in the ui font with its top left corner at position.
+    pub fn text(&mut self, position: [f32; 3], text: &str, size: f32, color: Color) {
+        // the font sheet only has printable ascii
+        let text = text.chars()
+            .map(|c| if (' '..='~').contains(&c) { c } else { '?' })
+            .collect::<String>();
+        let quads = text::character_quads_from_str(&text, vec![position[0], position[1], 0.0], size);
+        let glyphs = text::TextVecs::from_quads(&quads);
+
+        let base = self.vertices.len() as u32;
+        self.indices.extend(glyphs.indices.iter().map(|i| i + base));
+        self.vertices.extend(glyphs.vertices.iter().map(|vertex| DebugVertex {
+            position: [vertex.position[0], vertex.position[1], position[2]],
+            tex_coords: vertex.tex_coords,
+            color,
+        }));
+    }
+
+    /// A quad along the line, thickness wide.
+    fn line(&mut self, from: [f32; 2], to: [f32; 2], thickness: f32, color: Color) {
+        let (direction, length) = direction(from, to);
+        if length == 0.0 {
+            return;
+        }
+
+        let half = [-direction[1] * thickness / 2.0, direction[0] * thickness / 2.0];
+        let corners = [
+            [from[0] + half[0], from[1] + half[1]],
+            [from[0] - half[0], from[1] - half[1]],
+            [to[0] - half[0], to[1] - half[1]],
+            [to[0] + half[0], to[1] + half[1]],
+        ];
+        self.triangle([corners[0], corners[1], corners[2]], color);
+        self.triangle([corners[0], corners[2], corners[3]], color);
+    }
+
+    fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: Color) {
+        self.triangle([min, [max[0], min[1]], max], color);
+        self.triangle([min, max, [min[0], max[1]]], color);
+    }
+
+    /// Any winding, it is made counter clockwise so back face culling keeps it.
+    fn triangle(&mut self, points: [[f32; 2]; 3], color: Color) {
+        let [a, b, c] = points;
+        let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+        let points = if cross < 0.0 { [a, c, b] } else { points };
+
+        let tex_coords = [
+            (text::SOLID_UV.min[0] + text::SOLID_UV.max[0]) / 2.0,
+            (text::SOLID_UV.min[1] + text::SOLID_UV.max[1]) / 2.0,
+        ];
+        let base = self.vertices.len() as u32;
+        self.vertices.extend(points.iter().map(|point| DebugVertex { position: [point[0], point[1], Z], tex_coords, color }));
+        self.indices.extend([base, base + 1, base + 2]);
+    }
+
+    pub fn to_buffers(&self, device: &wgpu::Device) -> text::TextBuffers {
+        let vertices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Debug Vertex Buffer"),
+                contents: bytemuck::cast_slice(&self.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
+        let indices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Debug Index Buffer"),
+                contents: bytemuck::cast_slice(&self.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+
+        stats::count_upload(std::mem::size_of_val(&self.vertices[..]) as u64);
+        stats::count_upload(std::mem::size_of_val(&self.indices[..]) as u64);
+
+        text::TextBuffers {
+            vertices,
+            indices,
+            length: self.indices.len() as u32,
+        }
+    }
+}
+
+/// The unit vector from from to to, and the distance between them.
+fn direction(from: [f32; 2], to: [f32; 2]) -> ([f32; 2], f32) {
+    let delta = [to[0] - from[0], to[1] - from[1]];
+    let length = (delta[0] * delta[0] + delta[1] * delta[1]).sqrt();
+
+    if length == 0.0 {
+        ([0.0, 0.0], 0.0)
+    } else {
+        ([delta[0] / length, delta[1] / length], length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vertex and index counts of the world space triangles for whatever add draws.
+    fn counts(add: impl FnOnce(&mut DebugDraw)) -> (usize, usize) {
+        let mut debug = DebugDraw::default();
+        add(&mut debug);
+        let (world, _) = debug.tessellate();
+        (world.vertices.len(), world.indices.len())
+    }
+
+    #[test]
+    fn lines_and_rects() {
+        // a quad as two triangles, which don't share vertices
+        assert_eq!(counts(|debug| { debug.line([0.0, 0.0], [10.0, 5.0], RED); }), (6, 6));
+        assert_eq!(counts(|debug| { debug.line([3.0, 3.0], [3.0, 3.0], RED); }), (0, 0));
+
+        assert_eq!(counts(|debug| { debug.rect([0.0, 0.0], [10.0, 10.0], RED).filled(); }), (6, 6));
+        // one band per edge
+        assert_eq!(counts(|debug| { debug.rect([0.0, 0.0], [10.0, 10.0], RED); }), (24, 24));
+
+        // the line's shaft and a triangle for the head
+        assert_eq!(counts(|debug| { debug.arrow([0.0, 0.0], [0.0, 40.0], RED); }), (9, 9));
+    }
+
+    #[test]
+    fn circles() {
+        assert_eq!(counts(|debug| { debug.circle([0.0, 0.0], 8.0, RED).filled(); }), (CIRCLE_SEGMENTS * 3, CIRCLE_SEGMENTS * 3));
+        assert_eq!(counts(|debug| { debug.circle([0.0, 0.0], 8.0, RED); }), (CIRCLE_SEGMENTS * 6, CIRCLE_SEGMENTS * 6));
+    }
+
+    #[test]
+    fn grids_have_a_line_either_side_of_every_cell() {
+        // 4 vertical lines and 3 horizontal ones
+        assert_eq!(counts(|debug| { debug.grid([0.0, 0.0], [8.0, 8.0], [3, 2], RED); }), (7 * 6, 7 * 6));
+        // without cells the lines have no length, so nothing is drawn
+        assert_eq!(counts(|debug| { debug.grid([0.0, 0.0], [8.0, 8.0], [0, 0], RED); }), (0, 0));
+    }
+
+    #[test]
+    fn text_is_a_quad_per_character() {
+        assert_eq!(counts(|debug| { debug.text([0.0, 0.0], "abc", 12.0, WHITE); }), (3 * 4, 3 * 6));
+        // characters outside the font still take a quad, as '?'
+        assert_eq!(counts(|debug| { debug.text([0.0, 0.0], "é", 12.0, WHITE); }), (4, 6));
+
+        let mut debug = DebugDraw::default();
+        debug.text([0.0, 0.0], "ab", 12.0, WHITE);
+        debug.text([0.0, 0.0], "c", 12.0, WHITE);
+        let (world, _) = debug.tessellate();
+        // the second text's indices point at its own vertices
+        assert_eq!(world.indices[12..], world.indices[..6].iter().map(|i| i + 8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn triangles_are_counter_clockwise() {
+        let mut debug = DebugDraw::default();
+        debug.line([10.0, 0.0], [0.0, 0.0], RED);
+        debug.line([0.0, 10.0], [0.0, 0.0], RED).thickness(3.0);
+        debug.rect([0.0, 0.0], [10.0, 10.0], RED);
+        debug.circle([0.0, 0.0], 8.0, RED).filled();
+        debug.arrow([10.0, 10.0], [0.0, 0.0], RED);
+        let (world, _) = debug.tessellate();
+
+        for triangle in world.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| world.vertices[triangle[i] as usize].position);
+            let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            assert!(cross > 0.0, "{:?}", [a, b, c]);
+        }
+    }
+
+    #[test]
+    fn screen_shapes_and_lifetimes() {
+        let mut debug = DebugDraw::default();
+        debug.line([0.0, 0.0], [10.0, 0.0], RED);
+        debug.rect([0.0, 0.0], [10.0, 10.0], RED).filled().screen().seconds(1.0);
+
+        let (world, screen) = debug.tessellate();
+        assert_eq!((world.indices.len(), screen.indices.len()), (6, 6));
+
+        // the line was for one frame only
+        debug.frame(time::Duration::from_millis(600));
+        let (world, screen) = debug.tessellate();
+        assert_eq!((world.indices.len(), screen.indices.len()), (0, 6));
+
+        debug.frame(time::Duration::from_millis(600));
+        assert!(debug.is_empty());
+    }
+}
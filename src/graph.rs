@@ -0,0 +1,504 @@
+/*
+Render graph.
+
+Each frame passes are added with the resources they read and write, then
+execute works out an order from those declarations, drops passes whose output
+never reaches an imported resource, gives every transient texture a real one
+from the pool (sharing it with same sized transients whose lifetimes don't overlap)
+and records the passes into the encoder.
+
+Imported resources (the swapchain view, persistent targets, buffers) live
+outside the graph. Transient textures only exist for the frame, so nothing has
+to be recreated by hand on resize: a new size is a new description and the
+pool drops textures that went unused.
+
+Ordering: a pass reading a resource runs after every pass writing it, and
+passes writing the same resource run in the order they were added. Otherwise
+declaration order is kept, so passes can be added in whatever order is handy.
+
+execute_timed also has each pass timed on the GPU, for the stats overlay.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::*;
+
+use crate::stats;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl TextureDesc {
+    /// A colour target that later passes can sample.
+    pub fn color(width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            sample_count: 1,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    }
+
+    /// A multisampled colour target, only usable as an attachment to resolve from.
+    pub fn multisampled(width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            sample_count,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        }
+    }
+
+    /// A depth attachment, see texture::Texture::create_depth_texture_sized for why it isn't sampleable.
+    pub fn depth(width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            sample_count,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        }
+    }
+}
+
+enum Resource<'a> {
+    Texture(&'a wgpu::TextureView),
+    Buffer(&'a wgpu::Buffer),
+    Transient(TextureDesc),
+}
+
+/// What a pass gets to look its resources up in while recording.
+pub struct PassContext<'r> {
+    pub device: &'r wgpu::Device,
+    resources: &'r [Resource<'r>],
+    transient_views: &'r HashMap<ResourceId, &'r wgpu::TextureView>,
+}
+
+impl<'r> PassContext<'r> {
+    pub fn view(&self, id: ResourceId) -> &'r wgpu::TextureView {
+        match &self.resources[id.0] {
+            Resource::Texture(view) => view,
+            Resource::Transient(_) => self.transient_views[&id],
+            Resource::Buffer(_) => panic!("resource {:?} is a buffer, not a texture", id),
+        }
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> &'r wgpu::Buffer {
+        match &self.resources[id.0] {
+            Resource::Buffer(buffer) => buffer,
+            _ => panic!("resource {:?} is a texture, not a buffer", id),
+        }
+    }
+}
+
+type Record<'a> = Box<dyn for<'r> FnOnce(&PassContext<'r>, &mut wgpu::CommandEncoder) + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    record: Record<'a>,
+}
+
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource<'a>>,
+    names: Vec<String>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add_resource(&mut self, name: &str, resource: Resource<'a>) -> ResourceId {
+        self.resources.push(resource);
+        self.names.push(name.to_string());
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// A texture that outlives the frame, passes writing to it are never culled.
+    pub fn import_texture(&mut self, name: &str, view: &'a wgpu::TextureView) -> ResourceId {
+        self.add_resource(name, Resource::Texture(view))
+    }
+
+    pub fn import_buffer(&mut self, name: &str, buffer: &'a wgpu::Buffer) -> ResourceId {
+        self.add_resource(name, Resource::Buffer(buffer))
+    }
+
+    /// A texture that only exists for this frame, allocated (or shared) when the graph executes.
+    pub fn create_texture(&mut self, name: &str, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, Resource::Transient(desc))
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+        record: impl for<'r> FnOnce(&PassContext<'r>, &mut wgpu::CommandEncoder) + 'a,
+    ) {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+    }
+
+    /// Orders, allocates and records every pass into encoder.
+    pub fn execute(self, device: &wgpu::Device, pool: &mut TransientPool, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        self.record(device, pool, encoder, None)
+    }
+
+    /// execute, with timer timing each pass on the GPU.
+    pub fn execute_timed(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+        timer: &mut stats::GpuTimer,
+    ) -> Result<()> {
+        self.record(device, pool, encoder, Some(timer))
+    }
+
+    fn record(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+        mut timer: Option<&mut stats::GpuTimer>,
+    ) -> Result<()> {
+        let imported = self.resources.iter()
+            .map(|resource| !matches!(resource, Resource::Transient(_)))
+            .collect::<Vec<_>>();
+        let order = schedule(&self.passes, &self.names, &imported)?;
+
+        // first and last position in the order each transient is used at
+        let mut lifetimes: HashMap<ResourceId, (usize, usize)> = HashMap::new();
+        for (position, i) in order.iter().enumerate() {
+            let pass = &self.passes[*i];
+            for id in pass.reads.iter().chain(&pass.writes) {
+                if !imported[id.0] {
+                    lifetimes.entry(*id)
+                        .and_modify(|(_, last)| *last = position)
+                        .or_insert((position, position));
+                }
+            }
+        }
+
+        let mut transients = lifetimes.into_iter().collect::<Vec<_>>();
+        transients.sort_by_key(|(id, (first, _))| (*first, id.0));
+
+        let descs = transients.iter()
+            .map(|(id, (first, last))| {
+                let Resource::Transient(desc) = self.resources[id.0] else { unreachable!() };
+                (desc, *first, *last)
+            })
+            .collect::<Vec<_>>();
+        let (slots, slot_descs) = alias(&descs);
+
+        // a slot's texture is labelled after the first transient in it
+        let mut labels = vec![""; slot_descs.len()];
+        for ((id, _), slot) in transients.iter().zip(&slots) {
+            if labels[*slot].is_empty() {
+                labels[*slot] = &self.names[id.0];
+            }
+        }
+        let textures = pool.acquire(device, &slot_descs, &labels);
+
+        let transient_views = transients.iter().zip(&slots)
+            .map(|((id, _), slot)| (*id, &pool.textures[textures[*slot]].view))
+            .collect::<HashMap<_, _>>();
+
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+        let context = PassContext {
+            device,
+            resources: &self.resources,
+            transient_views: &transient_views,
+        };
+
+        for i in order {
+            let pass = passes[i].take().unwrap();
+            encoder.push_debug_group(&pass.name);
+            let timing = timer.as_mut().and_then(|timer| timer.begin_pass(encoder, &pass.name));
+            (pass.record)(&context, encoder);
+            if let (Some(timer), Some(index)) = (timer.as_mut(), timing) {
+                timer.end_pass(encoder, index);
+            }
+            encoder.pop_debug_group();
+        }
+
+        if let Some(timer) = timer {
+            timer.finish(encoder);
+        }
+
+        drop(transient_views);
+        pool.end_frame();
+
+        Ok(())
+    }
+}
+
+/// Indices of the passes that have to run, in an order that respects their dependencies.
+/// names and imported are indexed by resource.
+fn schedule(passes: &[Pass], names: &[String], imported: &[bool]) -> Result<Vec<usize>> {
+    let count = passes.len();
+
+    // dependencies[i] are the passes that have to run before pass i
+    let mut dependencies = vec![Vec::new(); count];
+    for id in (0..imported.len()).map(ResourceId) {
+        let writers = (0..count)
+            .filter(|i| passes[*i].writes.contains(&id))
+            .collect::<Vec<_>>();
+
+        // writes to the same resource happen in the order they were declared
+        for pair in writers.windows(2) {
+            dependencies[pair[1]].push(pair[0]);
+        }
+
+        for (i, pass) in passes.iter().enumerate() {
+            if pass.reads.contains(&id) && !writers.contains(&i) {
+                // reading something nobody wrote is fine for imports, for transients it is a mistake
+                ensure!(
+                    !writers.is_empty() || imported[id.0],
+                    "pass {} reads {} but no pass writes it", pass.name, names[id.0]
+                );
+                dependencies[i].extend(&writers);
+            }
+        }
+    }
+
+    // keep only passes that lead to an imported resource
+    let mut live = vec![false; count];
+    let mut stack = (0..count)
+        .filter(|i| passes[*i].writes.iter().any(|id| imported[id.0]))
+        .collect::<Vec<_>>();
+    while let Some(i) = stack.pop() {
+        if !live[i] {
+            live[i] = true;
+            stack.extend(&dependencies[i]);
+        }
+    }
+
+    // repeatedly take the first declared pass whose dependencies have all run
+    let mut order = Vec::new();
+    let mut done = vec![false; count];
+    while order.len() < live.iter().filter(|l| **l).count() {
+        let next = (0..count).find(|i| {
+            live[*i] && !done[*i] && dependencies[*i].iter().all(|d| done[*d])
+        });
+
+        match next {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            },
+            None => {
+                let stuck = (0..count)
+                    .filter(|i| live[*i] && !done[*i])
+                    .map(|i| passes[i].name.as_str())
+                    .collect::<Vec<_>>();
+                bail!("render graph has a cycle between {}", stuck.join(", "));
+            },
+        }
+    }
+
+    Ok(order)
+}
+
+/// Shares slots between transients with the same description whose lifetimes don't overlap.
+/// transients are (description, first use, last use) in order of first use, gives the slot
+/// of each and the description of every slot.
+fn alias(transients: &[(TextureDesc, usize, usize)]) -> (Vec<usize>, Vec<TextureDesc>) {
+    // description and the last position each slot is busy for
+    let mut slots: Vec<(TextureDesc, usize)> = Vec::new();
+
+    let assigned = transients.iter()
+        .map(|(desc, first, last)| {
+            let slot = match slots.iter().position(|(slot_desc, until)| slot_desc == desc && until < first) {
+                Some(slot) => slot,
+                None => {
+                    slots.push((*desc, *last));
+                    slots.len() - 1
+                },
+            };
+            slots[slot].1 = *last;
+            slot
+        })
+        .collect();
+
+    (assigned, slots.into_iter().map(|(desc, _)| desc).collect())
+}
+
+struct PooledTexture {
+    desc: TextureDesc,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    used: bool,
+}
+
+/// Physical textures behind transient graph resources, kept between frames.
+#[derive(Default)]
+pub struct TransientPool {
+    textures: Vec<PooledTexture>,
+}
+
+impl TransientPool {
+    /// A texture for each of this frame's slots, reusing last frame's where the description matches.
+    fn acquire(&mut self, device: &wgpu::Device, slots: &[TextureDesc], labels: &[&str]) -> Vec<usize> {
+        slots.iter().zip(labels)
+            .map(|(desc, label)| self.acquire_one(device, *desc, label))
+            .collect()
+    }
+
+    fn acquire_one(&mut self, device: &wgpu::Device, desc: TextureDesc, name: &str) -> usize {
+        let free = self.textures.iter().position(|t| t.desc == desc && !t.used);
+
+        let slot = free.unwrap_or_else(|| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(name),
+                size: wgpu::Extent3d {
+                    width: desc.width,
+                    height: desc.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.format,
+                usage: desc.usage,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            self.textures.push(PooledTexture { desc, texture, view, used: false });
+            self.textures.len() - 1
+        });
+
+        self.textures[slot].used = true;
+        slot
+    }
+
+    /// Frees everything for the next frame and drops textures that were not needed in this one.
+    fn end_frame(&mut self) {
+        self.textures.retain(|t| t.used);
+        for texture in &mut self.textures {
+            texture.used = false;
+        }
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn memory(&self) -> u64 {
+        self.textures.iter().map(|t| stats::texture_bytes(&t.texture)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(name: &str, reads: &[usize], writes: &[usize]) -> Pass<'static> {
+        Pass {
+            name: name.to_string(),
+            reads: reads.iter().copied().map(ResourceId).collect(),
+            writes: writes.iter().copied().map(ResourceId).collect(),
+            record: Box::new(|_, _| {}),
+        }
+    }
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("r{}", i)).collect()
+    }
+
+    #[test]
+    fn readers_run_after_writers() {
+        // 0 is the imported output, 1 and 2 transients
+        let passes = [
+            pass("composite", &[1, 2], &[0]),
+            pass("blur", &[1], &[2]),
+            pass("scene", &[], &[1]),
+        ];
+        let order = schedule(&passes, &names(3), &[true, false, false]).unwrap();
+
+        assert_eq!(order, [2, 1, 0]);
+    }
+
+    #[test]
+    fn writers_keep_declared_order() {
+        let passes = [
+            pass("overlay", &[], &[0]),
+            pass("unrelated", &[], &[1]),
+            pass("more overlay", &[], &[0]),
+        ];
+        let order = schedule(&passes, &names(2), &[true, true]).unwrap();
+
+        assert_eq!(order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn passes_not_leading_to_an_import_are_culled() {
+        let passes = [
+            pass("scene", &[], &[1]),
+            pass("unused", &[1], &[2]),
+            pass("post", &[1], &[0]),
+            pass("dead end", &[], &[3]),
+        ];
+        let order = schedule(&passes, &names(4), &[true, false, false, false]).unwrap();
+
+        assert_eq!(order, [0, 2]);
+    }
+
+    #[test]
+    fn cycles_and_unwritten_transients_are_errors() {
+        let passes = [
+            pass("a", &[2], &[1]),
+            pass("b", &[1], &[2]),
+            pass("out", &[1], &[0]),
+        ];
+        let e = schedule(&passes, &names(3), &[true, false, false]).unwrap_err();
+        assert_eq!(e.to_string(), "render graph has a cycle between a, b, out");
+
+        let passes = [pass("out", &[1], &[0])];
+        let e = schedule(&passes, &names(2), &[true, false]).unwrap_err();
+        assert_eq!(e.to_string(), "pass out reads r1 but no pass writes it");
+
+        // nobody has to write an import before it is read
+        assert_eq!(schedule(&passes, &names(2), &[true, true]).unwrap(), [0]);
+    }
+
+    #[test]
+    fn transients_share_slots_when_they_dont_overlap() {
+        let color = TextureDesc::color(64, 32, wgpu::TextureFormat::Rgba8Unorm);
+        let other_size = TextureDesc::color(32, 32, wgpu::TextureFormat::Rgba8Unorm);
+
+        // a ping pong chain, each target read by the pass after the one writing it
+        let (slots, descs) = alias(&[(color, 0, 1), (color, 1, 2), (color, 2, 3)]);
+        assert_eq!(slots, [0, 1, 0]);
+        assert_eq!(descs, [color, color]);
+
+        // the same lifetimes but a different size can't share
+        let (slots, descs) = alias(&[(color, 0, 1), (color, 1, 2), (other_size, 2, 3)]);
+        assert_eq!(slots, [0, 1, 2]);
+        assert_eq!(descs, [color, color, other_size]);
+
+        // all alive at once
+        let (slots, _) = alias(&[(color, 0, 3), (color, 1, 2), (color, 2, 2)]);
+        assert_eq!(slots, [0, 1, 2]);
+    }
+}
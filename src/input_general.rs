@@ -16,6 +16,8 @@ pub struct Input {
     prev_mouse: Box<[bool]>,
     now_mouse_pos: MousePos<f64>,
     prev_mouse_pos: MousePos<f64>,
+    // lines scrolled since the last frame, positive away from the user
+    scroll: f32,
 }
 impl Default for Input {
     fn default() -> Self {
@@ -26,10 +28,10 @@ impl Default for Input {
             prev_mouse: vec![false; 16].into_boxed_slice(),
             now_mouse_pos: MousePos { x: 0.0, y: 0.0 },
             prev_mouse_pos: MousePos { x: 0.0, y: 0.0 },
+            scroll: 0.0,
         }
     }
 }
-#[allow(dead_code)]
 impl Input {
     pub fn is_key_down(&self, kc: Key) -> bool {
         self.now_keys[kc as usize]
@@ -74,6 +76,9 @@ impl Input {
             y: self.now_mouse_pos.y - self.prev_mouse_pos.y,
         }
     }
+    pub fn scroll_delta(&self) -> f32 {
+        self.scroll
+    }
     pub fn key_axis(&self, down: Key, up: Key) -> f32 {
         (if self.is_key_down(down) { -1.0 } else { 0.0 })
             + (if self.is_key_down(up) { 1.0 } else { 0.0 })
@@ -82,6 +87,7 @@ impl Input {
         self.prev_keys.copy_from_slice(&self.now_keys);
         self.prev_mouse.copy_from_slice(&self.now_mouse);
         self.prev_mouse_pos = self.now_mouse_pos;
+        self.scroll = 0.0;
     }
     pub fn handle_key_event(&mut self, ke: winit::event::KeyboardInput) {
         if let winit::event::KeyboardInput {
@@ -114,4 +120,11 @@ impl Input {
     pub fn handle_mouse_move(&mut self, position: MousePos<f64>) {
         self.now_mouse_pos = position;
     }
+    pub fn handle_mouse_wheel(&mut self, delta: winit::event::MouseScrollDelta) {
+        self.scroll += match delta {
+            winit::event::MouseScrollDelta::LineDelta(_, y) => y,
+            // roughly a line's worth of pixels
+            winit::event::MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+        };
+    }
 }
\ No newline at end of file
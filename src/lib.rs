@@ -0,0 +1,25 @@
+// The renderer's modules, shared with the demo in main.rs and the asset tools in src/bin
+
+pub mod archive;
+pub mod atlas;
+pub mod blend;
+pub mod camera;
+pub mod compressed;
+pub mod debug_draw;
+pub mod graph;
+pub mod input_general;
+pub mod loader;
+pub mod material;
+pub mod mipmap;
+pub mod nine_slice;
+pub mod pipeline;
+pub mod post;
+pub mod resources;
+pub mod shader;
+pub mod shape;
+pub mod sprite;
+pub mod sprite_sheet;
+pub mod stats;
+pub mod text;
+pub mod texture;
+pub mod widget;
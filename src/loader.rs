@@ -0,0 +1,315 @@
+/*
+Background asset loading.
+
+Files are read and decoded on a small pool of worker threads, the main thread
+only does the GPU upload in poll. Until a texture arrives (or if it fails to
+load) lookups return a placeholder so rendering never has to wait. Texture
+arrays load the same way but have no placeholder, they are None until ready.
+*/
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::compressed;
+use crate::resources;
+use crate::stats;
+use crate::texture;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub(crate) u32);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureArrayHandle(u32);
+
+/// What became ready in a call to AssetLoader::poll.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ready {
+    Texture(TextureHandle),
+    TextureArray(TextureArrayHandle),
+}
+
+enum Job {
+    // options of None means read them from the sidecar file
+    Texture(TextureHandle, String, Option<texture::TextureOptions>),
+    TextureArray(TextureArrayHandle, Vec<String>),
+}
+
+enum Pixels {
+    Rgba(image::RgbaImage),
+    // only kept compressed when the device can take it as is
+    Compressed(compressed::CompressedImage),
+}
+
+struct Decoded {
+    pixels: Pixels,
+    options: texture::TextureOptions,
+}
+
+enum Loaded {
+    Texture(TextureHandle, String, anyhow::Result<Decoded>),
+    TextureArray(TextureArrayHandle, anyhow::Result<(Vec<image::RgbaImage>, texture::TextureOptions)>),
+}
+
+enum Slot<T> {
+    Loading,
+    Ready(Box<T>),
+    Failed,
+}
+
+pub struct AssetLoader {
+    jobs: Option<mpsc::Sender<Job>>,
+    loaded: mpsc::Receiver<Loaded>,
+    workers: Vec<thread::JoinHandle<()>>,
+    textures: HashMap<TextureHandle, Slot<texture::Texture>>,
+    by_name: HashMap<String, TextureHandle>,
+    arrays: HashMap<TextureArrayHandle, Slot<texture::TextureArray>>,
+    arrays_by_names: HashMap<Vec<String>, TextureArrayHandle>,
+    next_handle: u32,
+    placeholder: texture::Texture,
+}
+
+impl AssetLoader {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let worker_count = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(2)
+            .clamp(1, 4);
+
+        let bc_supported = device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (loaded_tx, loaded_rx) = mpsc::channel::<Loaded>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..worker_count)
+            .map(|i| {
+                let job_rx = Arc::clone(&job_rx);
+                let loaded_tx = loaded_tx.clone();
+
+                thread::Builder::new()
+                    .name(format!("asset-loader-{}", i))
+                    .spawn(move || worker(job_rx, loaded_tx, bc_supported))
+                    .unwrap()
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_tx),
+            loaded: loaded_rx,
+            workers,
+            textures: HashMap::new(),
+            by_name: HashMap::new(),
+            arrays: HashMap::new(),
+            arrays_by_names: HashMap::new(),
+            next_handle: 0,
+            placeholder: texture::Texture::placeholder(device, queue),
+        }
+    }
+
+    /// Queues a texture for loading with the options from its sidecar file, if it has one.
+    /// Requesting the same file twice returns the same handle.
+    pub fn request_texture(&mut self, file_name: &str) -> TextureHandle {
+        self.request(file_name, None)
+    }
+
+    /// Like request_texture but ignores any sidecar file.
+    pub fn request_texture_with(&mut self, file_name: &str, options: texture::TextureOptions) -> TextureHandle {
+        self.request(file_name, Some(options))
+    }
+
+    fn request(&mut self, file_name: &str, options: Option<texture::TextureOptions>) -> TextureHandle {
+        if let Some(handle) = self.by_name.get(file_name) {
+            return *handle;
+        }
+
+        let handle = TextureHandle(self.next_handle);
+        self.next_handle += 1;
+
+        self.textures.insert(handle, Slot::Loading);
+        self.by_name.insert(file_name.to_string(), handle);
+        self.jobs.as_ref()
+            .unwrap()
+            .send(Job::Texture(handle, file_name.to_string(), options))
+            .unwrap();
+
+        handle
+    }
+
+    /// Queues same sized images for loading into the layers of one texture array, in the
+    /// order given. The first image's sidecar decides the options for the whole array.
+    pub fn request_texture_array(&mut self, file_names: &[&str]) -> TextureArrayHandle {
+        let names = file_names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        if let Some(handle) = self.arrays_by_names.get(&names) {
+            return *handle;
+        }
+
+        let handle = TextureArrayHandle(self.next_handle);
+        self.next_handle += 1;
+
+        self.arrays.insert(handle, Slot::Loading);
+        self.arrays_by_names.insert(names.clone(), handle);
+        self.jobs.as_ref()
+            .unwrap()
+            .send(Job::TextureArray(handle, names))
+            .unwrap();
+
+        handle
+    }
+
+    /// Uploads everything that finished decoding since the last call, returns what became ready.
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Ready> {
+        let mut ready = Vec::new();
+
+        while let Ok(loaded) = self.loaded.try_recv() {
+            match loaded {
+                Loaded::Texture(handle, name, Ok(decoded)) => {
+                    let texture = match decoded.pixels {
+                        Pixels::Rgba(img) => texture::Texture::from_image_with_options(
+                            device, queue, &image::DynamicImage::ImageRgba8(img), Some(&name), &decoded.options,
+                        ),
+                        Pixels::Compressed(img) => texture::Texture::from_compressed(
+                            device, queue, &img, Some(&name), &decoded.options,
+                        ),
+                    };
+                    match texture {
+                        Ok(texture) => {
+                            self.textures.insert(handle, Slot::Ready(Box::new(texture)));
+                            ready.push(Ready::Texture(handle));
+                        },
+                        Err(e) => {
+                            eprintln!("failed to upload {}: {:?}", name, e);
+                            self.textures.insert(handle, Slot::Failed);
+                        },
+                    }
+                },
+                Loaded::Texture(handle, name, Err(e)) => {
+                    eprintln!("failed to load {}: {:?}", name, e);
+                    self.textures.insert(handle, Slot::Failed);
+                },
+                Loaded::TextureArray(handle, images) => {
+                    let array = images.and_then(|(images, options)| {
+                        texture::TextureArray::from_images(device, queue, &images, Some("texture array"), &options)
+                    });
+                    match array {
+                        Ok(array) => {
+                            self.arrays.insert(handle, Slot::Ready(Box::new(array)));
+                            ready.push(Ready::TextureArray(handle));
+                        },
+                        Err(e) => {
+                            eprintln!("failed to load texture array: {:?}", e);
+                            self.arrays.insert(handle, Slot::Failed);
+                        },
+                    }
+                },
+            }
+        }
+
+        ready
+    }
+
+    pub fn is_ready(&self, handle: TextureHandle) -> bool {
+        matches!(self.textures.get(&handle), Some(Slot::Ready(_)))
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.textures.values().any(|slot| matches!(slot, Slot::Loading))
+            || self.arrays.values().any(|slot| matches!(slot, Slot::Loading))
+    }
+
+    /// The loaded texture, or the placeholder while it is still loading or if it failed.
+    pub fn texture(&self, handle: TextureHandle) -> &texture::Texture {
+        match self.textures.get(&handle) {
+            Some(Slot::Ready(texture)) => texture,
+            _ => &self.placeholder,
+        }
+    }
+
+    /// The loaded texture array, None while it is still loading or if it failed.
+    pub fn texture_array(&self, handle: TextureArrayHandle) -> Option<&texture::TextureArray> {
+        match self.arrays.get(&handle) {
+            Some(Slot::Ready(array)) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Memory taken by every loaded texture and the placeholder.
+    pub fn memory(&self) -> u64 {
+        let loaded = self.textures.values()
+            .map(|slot| match slot {
+                Slot::Ready(texture) => stats::texture_bytes(&texture.texture),
+                _ => 0,
+            })
+            .sum::<u64>();
+        let arrays = self.arrays.values()
+            .map(|slot| match slot {
+                Slot::Ready(array) => stats::texture_bytes(&array.texture),
+                _ => 0,
+            })
+            .sum::<u64>();
+
+        loaded + arrays + stats::texture_bytes(&self.placeholder.texture)
+    }
+
+    pub fn placeholder(&self) -> &texture::Texture {
+        &self.placeholder
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // closing the channel lets the workers fall out of their loop
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(jobs: Arc<Mutex<mpsc::Receiver<Job>>>, loaded: mpsc::Sender<Loaded>, bc_supported: bool) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        let result = match job {
+            Ok(Job::Texture(handle, name, options)) => {
+                let decoded = pollster::block_on(decode_texture(&name, options, bc_supported));
+                Loaded::Texture(handle, name, decoded)
+            },
+            Ok(Job::TextureArray(handle, names)) => {
+                let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+                Loaded::TextureArray(handle, pollster::block_on(resources::load_array_images(&names)))
+            },
+            Err(_) => return,
+        };
+
+        if loaded.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+async fn decode_texture(
+    name: &str,
+    options: Option<texture::TextureOptions>,
+    bc_supported: bool,
+) -> anyhow::Result<Decoded> {
+    let mut options = match options {
+        Some(options) => options,
+        None => resources::load_texture_options(name).await?,
+    };
+    let bytes = resources::load_binary(name).await?;
+
+    let pixels = if compressed::CompressedImage::is_container(&bytes) {
+        let img = compressed::CompressedImage::parse(&bytes)?;
+        if bc_supported && img.is_block_aligned() && !options.premultiply_alpha {
+            Pixels::Compressed(img)
+        } else {
+            // decompress here rather than on the main thread
+            options = options.color_space(if img.srgb { texture::ColorSpace::Srgb } else { texture::ColorSpace::Linear });
+            Pixels::Rgba(img.decompress()?)
+        }
+    } else {
+        Pixels::Rgba(image::load_from_memory(&bytes)?.to_rgba8())
+    };
+
+    Ok(Decoded { pixels, options })
+}
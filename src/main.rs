@@ -6,23 +6,84 @@ use winit::{
     event_loop::ControlFlow,
     window::Window,
 };
-use std::{f32::consts::SQRT_2, time};
+use std::{cell::RefCell, collections::HashMap, f32::consts::SQRT_2, ops::Range, rc::Rc, time};
 
-mod text;
-mod texture;
-mod resources;
-mod camera;
-mod input_general;
+use trying_somin::{
+    blend, camera, debug_draw, graph, input_general, loader, material, nine_slice, pipeline, post, resources,
+    shader, shape, sprite, stats, text, texture, widget,
+};
 
 fn main() {
     pollster::block_on(run());
 }
 
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+// Off to draw everything in submission order without depth testing
+const USE_DEPTH_BUFFER: bool = true;
+
+// Lowered to what the adapter supports, 1 turns multisampling off
+const MSAA_SAMPLES: u32 = 4;
+
 struct UIRenderable;
+
+/// All of a layer's quads in one buffer, opaque ones first, with the index range to draw with each blend mode.
+struct Layer {
+    buffers: text::TextBuffers,
+    batches: Vec<(Batch, Range<u32>)>,
+}
+
+/// Widgets inside one clip rectangle, scissor is x, y, width and height from the top left.
+struct WidgetGroup {
+    scissor: Option<[u32; 4]>,
+    shapes: Layer,
+    images: Layer,
+    text: Layer,
+}
+
+/// What consecutive quads in a layer share, the material's pipeline replaces the layer's own one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Batch {
+    blend_mode: blend::BlendMode,
+    material: Option<material::PipelineKey>,
+}
+
+impl From<blend::BlendMode> for Batch {
+    fn from(blend_mode: blend::BlendMode) -> Self {
+        Batch { blend_mode, material: None }
+    }
+}
+
+/// A camera drawing the scene into its own target, which can then be sampled like a sprite.
+struct OffscreenView {
+    camera: camera::Camera,
+    binding: camera::CameraBinding,
+    target: texture::RenderTarget,
+    clear_color: wgpu::Color,
+    // the target, to sample in the overlay pass
+    bind_group: wgpu::BindGroup,
+    /// Where the overlay pass shows the target, x, y, width and height in pixels from the bottom left.
+    inset: Option<[f32; 4]>,
+}
 struct Framerate (u32);
 
 async fn run() {
     env_logger::init();
+
+    // A packed archive next to the executable takes priority over the loose res/ files
+    if let Some(pak) = std::env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join("res.pak"))) {
+        if pak.exists() {
+            if let Err(e) = resources::mount_archive(&pak) {
+                eprintln!("could not mount {}: {:?}", pak.display(), e);
+            }
+        }
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -30,7 +91,7 @@ async fn run() {
 
     // Testing out new system
 
-    let a_quad = text::CharacterQuad {
+    let _a_quad = text::CharacterQuad {
         position: [100.0, 100.0, 0.0],
         size: [50.0, 100.0],
         character: 97,  
@@ -40,10 +101,68 @@ async fn run() {
 
     let a_text = text::character_quads_from_str("Test", vec![150.0, 150.0, 0.0], 50.0);
 
-    let a = state.world.spawn((UIRenderable, a_text));
+    let _a = state.world.spawn((UIRenderable, a_text));
 
     state.ui_changed = true;
 
+    let mut panel = shape::ShapeMesh::new(-0.1);
+    let outline = shape::rounded_rect([140.0, 90.0], [360.0, 160.0], 12.0);
+    panel.fill(&outline, [0.9, 0.9, 0.8, 1.0]).unwrap()
+        .stroke(&outline, &shape::Stroke::new(3.0), [0.2, 0.2, 0.3, 1.0]).unwrap();
+
+    let _panel = state.world.spawn((panel,));
+
+    state.shapes_changed = true;
+
+    let world = &mut state.world;
+    let log_event = || widget::OnEvent::new(|event| log::debug!("{:?}", event));
+    world.spawn((widget::Widget::new([20.0, 420.0], [140.0, 28.0]), widget::Button::new("Button"), log_event()));
+    world.spawn((widget::Widget::new([20.0, 380.0], [140.0, 20.0]), widget::Checkbox::new("Checkbox", true), log_event()));
+    world.spawn((widget::Widget::new([20.0, 350.0], [140.0, 20.0]), widget::Slider::new(0.0, 1.0, 0.3), log_event()));
+    world.spawn((widget::Widget::new([20.0, 320.0], [140.0, 12.0]), widget::ProgressBar::new(0.6)));
+
+    world.spawn((widget::Widget::new([20.0, 460.0], [32.0, 32.0]), widget::Image::new(texture::UvRect::FULL, 0)));
+
+    let list = world.spawn((widget::Widget::new([180.0, 420.0], [160.0, 100.0]), widget::ScrollView::new(10.0 * 20.0), log_event()));
+    for i in 0..10 {
+        let label = format!("Item {}", i);
+        world.spawn((widget::Widget::new([8.0, -20.0 * i as f32], [144.0, 20.0]).child_of(list), widget::Label::new(&label)));
+    }
+
+    state.widgets.changed = true;
+
+    // one layer for now, the sprite and the image widget above show once it has loaded
+    state.set_sprite_textures(&["texture1_letters.png"]);
+    state.world.spawn((sprite::SpriteQuad {
+        position: [420.0, 220.0, 0.0],
+        size: [128.0, 128.0],
+        uv: texture::UvRect::FULL,
+        layer: 0,
+    },));
+
+    // the same sprite again, half flashed to white
+    let flash = state.materials.add(&state.device, &state.loader, material::Material::flash([1.0, 1.0, 1.0, 1.0]))
+        .and_then(|flash| {
+            state.materials.set_param(&state.queue, flash, "amount", material::Param::Float(0.5))?;
+            Ok(flash)
+        });
+    match flash {
+        Ok(flash) => {
+            state.world.spawn((sprite::SpriteQuad {
+                position: [560.0, 220.0, 0.0],
+                size: [128.0, 128.0],
+                uv: texture::UvRect::FULL,
+                layer: 0,
+            }, flash));
+        },
+        Err(e) => eprintln!("{:?}", e),
+    }
+
+    // the scene zoomed out, in the bottom right corner
+    let minimap = camera::Camera::new(1600.0, 1200.0, 0.0, 0.0);
+    let minimap_color = wgpu::Color { r: 0.05, g: 0.05, b: 0.1, a: 1.0 };
+    state.add_offscreen_view(minimap, 200, 150, minimap_color, Some([580.0, 16.0, 200.0, 150.0]));
+
     //state.camera.modify_position(10.0, 10.0);
     //state.camera_uniform.update_proj(&state.camera);
     //state.queue.write_buffer(&state.camera_buffer, 0, bytemuck::cast_slice(&[state.camera_uniform]));
@@ -56,17 +175,15 @@ async fn run() {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window().id() => if !state.input(event) { 
-                match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    },
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    },
-                    _ => {},
-                }
+            } if window_id == state.window().id() && !state.input(event) => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
+                },
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    state.resize(**new_inner_size);
+                },
+                _ => {},
             },
             Event::RedrawRequested(window_id) if window_id == state.window().id() => {
                 let now = time::Instant::now();
@@ -98,15 +215,64 @@ struct State {
     window: Window,
     world: hecs::World,
     input: input_general::Input,
-    ui_layer: text::TextBuffers,
+    target_format: pipeline::TargetFormat,
+    pipeline_cache: pipeline::PipelineCache,
+    transient_pool: RefCell<graph::TransientPool>,
+    ui_layer: Layer,
+    // for each batch of ui_layer, how many shape batches are behind it
+    ui_shapes_behind: Vec<usize>,
     ui_changed: bool,
-    ui_render_pipeline: wgpu::RenderPipeline,
+    ui_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    // the same text in screen space, for overlays
+    screen_ui_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    ui_pipeline_layout: wgpu::PipelineLayout,
+    // vector shapes alongside the ui, alpha blended for their antialiased edges
+    shape_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    // a batch per shape, back to front, and the z of each
+    shape_layer: Layer,
+    shape_depths: Vec<f32>,
+    shapes_changed: bool,
+    // screen space shapes and sprites, for widgets
+    screen_shape_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    screen_sprite_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    widgets: widget::Widgets,
+    widget_groups: Vec<WidgetGroup>,
+    sprite_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    sprite_pipeline_layout: wgpu::PipelineLayout,
+    shader_watcher: Option<shader::Watcher>,
+    shader_errors: Vec<String>,
+    shader_error_layer: Layer,
+    stats: stats::Stats,
+    stats_layer: Layer,
+    debug: debug_draw::DebugDraw,
+    // one alpha blended pipeline each, for the world and screen space shapes
+    debug_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    screen_debug_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    debug_layer: Layer,
+    screen_debug_layer: Layer,
+    gpu_timer: Option<RefCell<stats::GpuTimer>>,
+    sprite_array_bind_group_layout: wgpu::BindGroupLayout,
+    /// What set_sprite_textures last asked the loader for, bound once it arrives.
+    sprite_textures: Option<loader::TextureArrayHandle>,
+    sprite_array: Option<wgpu::BindGroup>,
+    sprite_layer: Layer,
+    sprites_changed: bool,
+    /// Sprites and panels in the world when sprite_layer was built, to see when some are added or removed.
+    sprite_count: usize,
     camera_bind_group: wgpu::BindGroup,
     camera: camera::Camera,
     camera_uniform: camera::CameraUniform,
     camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    offscreen_views: Vec<OffscreenView>,
+    // copies offscreen targets into their insets
+    offscreen_pipelines: HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    post: post::PostChain,
+    materials: material::Materials,
     ui_bind_group: wgpu::BindGroup,
-    ui_texture: texture::Texture,
+    ui_texture_bind_group_layout: wgpu::BindGroupLayout,
+    ui_texture: loader::TextureHandle,
+    loader: loader::AssetLoader,
     framerate_entity: hecs::Entity,
 }
 
@@ -133,7 +299,14 @@ impl State {
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
+                // block compressed textures are uploaded as is when the adapter can sample them,
+                // adapter specific format features allow sample counts other than 1 and 4,
+                // timestamp queries time render passes for the stats overlay
+                features: adapter.features() & (
+                    wgpu::Features::TEXTURE_COMPRESSION_BC
+                    | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | wgpu::Features::TIMESTAMP_QUERY
+                ),
                 limits: wgpu::Limits::default(),
                 label: None,
             },
@@ -158,6 +331,22 @@ impl State {
         };
         surface.configure(&device, &config);
 
+        let mut sample_formats = vec![config.format];
+        if USE_DEPTH_BUFFER {
+            sample_formats.push(texture::Texture::DEPTH_FORMAT);
+        }
+        let sample_count = supported_sample_count(&adapter, &device, &sample_formats, MSAA_SAMPLES);
+        if sample_count != MSAA_SAMPLES {
+            eprintln!("{}x MSAA is not supported, using {}x", MSAA_SAMPLES, sample_count);
+        }
+
+        // the depth and multisampled textures themselves are transients in the render graph
+        let target_format = pipeline::TargetFormat {
+            color: config.format,
+            depth: USE_DEPTH_BUFFER.then_some(texture::Texture::DEPTH_FORMAT),
+            sample_count,
+        };
+
         let mut world = hecs::World::new();
 
         let input = input_general::Input::default();
@@ -175,23 +364,7 @@ impl State {
             }
         );
 
-        let camera_bind_group_layout = device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-                ],
-                label: Some("camera_bind_group_layout"),
-            }
-        );
+        let camera_bind_group_layout = camera::create_bind_group_layout(&device);
 
         let camera_bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor { 
@@ -207,17 +380,19 @@ impl State {
         );
 
         let ui_empty = text::TextVecs {vertices: Vec::new(), indices: Vec::new()};
-        let ui_layer = ui_empty.to_buffers(&device);
+        let ui_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let shader_error_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let stats_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let shape_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let debug_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let screen_debug_layer = Layer { buffers: ui_empty.to_buffers(&device), batches: Vec::new() };
+        let gpu_timer = stats::GpuTimer::new(&device, &queue).map(RefCell::new);
         let ui_changed = false;
 
-        let ui_texture1 = 
-            resources::load_texture("texture1_letters.png", &device, &queue)
-            .await
-            .unwrap();
-        let ui_texture = 
-            resources::load_texture("all_16x16.png", &device, &queue)
-            .await
-            .unwrap();
+        let mut loader = loader::AssetLoader::new(&device, &queue);
+
+        let _ui_texture1 = loader.request_texture("texture1_letters.png");
+        let ui_texture = loader.request_texture("all_16x16.png");
 
         let ui_texture_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
@@ -243,24 +418,11 @@ impl State {
             }
         );
 
-        let ui_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &ui_texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&ui_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&ui_texture.sampler),
-                    }
-                ],
-                label: Some("ui_texture_bind_group"),
-            }
-        );
+        // Starts out on the placeholder, swapped in update once the sheet has loaded
+        let ui_bind_group = loader.texture(ui_texture)
+            .bind_group(&device, &ui_texture_bind_group_layout, "ui_texture_bind_group");
 
-        let ui_render_pipeline_layout = device.create_pipeline_layout(
+        let ui_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
@@ -271,28 +433,30 @@ impl State {
             }
         );
 
-        let ui_render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Text Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
-            };
+        let sprite_array_bind_group_layout = sprite::create_array_bind_group_layout(&device);
 
-            create_render_pipeline(
-                &device,
-                &ui_render_pipeline_layout,
-                config.format,
-                None,
-                &[
-                    text::CharacterVertex::desc(), 
+        let sprite_pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Sprite Pipeline Layout"),
+                bind_group_layouts: &[
+                    &sprite_array_bind_group_layout,
+                    &camera_bind_group_layout,
                 ],
-                shader,
-            )
-        };
+                push_constant_ranges: &[],
+            }
+        );
+
+        let sprite_layer = Layer { buffers: sprite::SpriteVecs::default().to_buffers(&device), batches: Vec::new() };
+
+        let mut errors = Vec::new();
+
+        // empty, so the scene goes straight to the swapchain until an effect is pushed
+        let post = post::PostChain::new(&device, &queue, config.format);
 
         let framerate_text = text::character_quads_from_str("0", vec![20.0, 20.0, 0.0], 20.0);
         let framerate_entity = world.spawn((UIRenderable, Framerate(0), framerate_text));
 
-        State {
+        let mut state = State {
             surface,
             device,
             queue,
@@ -302,22 +466,275 @@ impl State {
             world,
             input,
             camera,
+            target_format,
+            pipeline_cache: pipeline::PipelineCache::default(),
+            transient_pool: RefCell::new(graph::TransientPool::default()),
             ui_layer,
+            ui_shapes_behind: Vec::new(),
             ui_changed,
-            ui_render_pipeline,
+            ui_pipelines: HashMap::new(),
+            screen_ui_pipelines: HashMap::new(),
+            ui_pipeline_layout,
+            shape_pipelines: HashMap::new(),
+            shape_layer,
+            shape_depths: Vec::new(),
+            shapes_changed: false,
+            screen_shape_pipelines: HashMap::new(),
+            screen_sprite_pipelines: HashMap::new(),
+            widgets: widget::Widgets::default(),
+            widget_groups: Vec::new(),
+            sprite_pipelines: HashMap::new(),
+            sprite_pipeline_layout,
+            shader_watcher: shader::HOT_RELOAD.then(shader::Watcher::new),
+            shader_errors: Vec::new(),
+            shader_error_layer,
+            stats: stats::Stats::default(),
+            stats_layer,
+            debug: debug_draw::DebugDraw::default(),
+            debug_pipelines: HashMap::new(),
+            screen_debug_pipelines: HashMap::new(),
+            debug_layer,
+            screen_debug_layer,
+            gpu_timer,
+            sprite_array_bind_group_layout,
+            sprite_textures: None,
+            sprite_array: None,
+            sprite_layer,
+            sprites_changed: false,
+            sprite_count: 0,
             camera_bind_group,
             camera_uniform,
             camera_buffer,
+            camera_bind_group_layout,
+            offscreen_views: Vec::new(),
+            offscreen_pipelines: HashMap::new(),
+            post,
+            materials: material::Materials::default(),
             ui_bind_group,
+            ui_texture_bind_group_layout,
             ui_texture,
+            loader,
             framerate_entity,
-        }
+        };
+
+        // a set whose shader doesn't build stays empty, so what it draws is skipped and the error shown
+        errors.extend(state.create_pipelines());
+        state.set_shader_errors(errors);
+
+        state
     }
 
     fn window(&self) -> &Window {
         &self.window
     }
 
+    /// Loads the textures SpriteQuad layers index into in the background, they all need
+    /// to be the same size. Sprites are drawn once the array is ready.
+    fn set_sprite_textures(&mut self, file_names: &[&str]) {
+        let handle = self.loader.request_texture_array(file_names);
+        self.sprite_textures = Some(handle);
+
+        // already loaded from an earlier request
+        if self.loader.texture_array(handle).is_some() {
+            self.bind_sprite_textures(handle);
+        }
+    }
+
+    fn bind_sprite_textures(&mut self, handle: loader::TextureArrayHandle) {
+        if let Some(array) = self.loader.texture_array(handle) {
+            self.sprite_array = Some(array.bind_group(&self.device, &self.sprite_array_bind_group_layout, "sprite_array_bind_group"));
+            self.sprites_changed = true;
+        }
+    }
+
+    /// Adds a camera that renders into its own target each frame, shown on top of the frame
+    /// at inset if there is one. Returns its index in offscreen_views.
+    fn add_offscreen_view(&mut self, camera: camera::Camera, width: u32, height: u32, clear_color: wgpu::Color, inset: Option<[f32; 4]>) -> usize {
+        let binding = camera::CameraBinding::new(&self.device, &self.camera_bind_group_layout, &camera);
+        // the scene pipeline is built for the surface format, so targets share it
+        let target = texture::RenderTarget::new(&self.device, width, height, self.config.format, self.target_format.depth.is_some(), self.target_format.sample_count, "Offscreen Target");
+        let bind_group = target.color.bind_group(&self.device, &self.ui_texture_bind_group_layout, "offscreen_bind_group");
+
+        self.offscreen_views.push(OffscreenView { camera, binding, target, clear_color, bind_group, inset });
+        self.offscreen_views.len() - 1
+    }
+
+    /// Builds the text, sprite, shape and debug pipeline sets, a set whose shader doesn't build keeps what it had.
+    fn create_pipelines(&mut self) -> Vec<anyhow::Error> {
+        let mut errors = Vec::new();
+
+        // overlays are drawn after post processing, straight into the single sampled output
+        let overlay_format = self.overlay_format();
+        let scene_format = self.target_format;
+        let process = |file: &str, screen_space: bool| shader::Preprocessor::new().screen_space(screen_space).process_file(file);
+
+        let sets = [
+            (&mut self.ui_pipelines, &self.ui_pipeline_layout, scene_format, text::CharacterVertex::desc(), process("text.wgsl", false)),
+            (&mut self.screen_ui_pipelines, &self.ui_pipeline_layout, overlay_format, text::CharacterVertex::desc(), process("text.wgsl", true)),
+            (&mut self.sprite_pipelines, &self.sprite_pipeline_layout, scene_format, sprite::SpriteVertex::desc(), process("sprite_array.wgsl", false)),
+        ];
+        for (pipelines, layout, target_format, vertex_layout, shader) in sets {
+            let set = shader.and_then(|shader| self.pipeline_cache.blend_set(&self.device, layout, target_format, vertex_layout, &shader));
+            match set {
+                Ok(set) => *pipelines = set,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        // the ones only ever drawn alpha blended
+        let alpha_sets = [
+            (&mut self.debug_pipelines, &self.ui_pipeline_layout, overlay_format, vec![debug_draw::DebugVertex::desc()], process("debug.wgsl", false)),
+            (&mut self.screen_debug_pipelines, &self.ui_pipeline_layout, overlay_format, vec![debug_draw::DebugVertex::desc()], process("debug.wgsl", true)),
+            (&mut self.shape_pipelines, &self.ui_pipeline_layout, scene_format, vec![shape::ShapeVertex::desc()], process("shape.wgsl", false)),
+            (&mut self.screen_shape_pipelines, &self.ui_pipeline_layout, overlay_format, vec![shape::ShapeVertex::desc()], process("shape.wgsl", true)),
+            (&mut self.screen_sprite_pipelines, &self.sprite_pipeline_layout, overlay_format, vec![sprite::SpriteVertex::desc()], process("sprite_array.wgsl", true)),
+            // a fullscreen triangle, the viewport puts it in the inset. Blended so it leaves the overlay depth alone
+            (&mut self.offscreen_pipelines, &self.ui_pipeline_layout, overlay_format, Vec::new(), process("blit.wgsl", false)),
+        ];
+        for (pipelines, layout, target_format, vertex_layouts, shader) in alpha_sets {
+            let mode = blend::BlendMode::Alpha;
+            let pipeline = shader.and_then(|shader| {
+                self.pipeline_cache.get(&self.device, layout, target_format, mode, &vertex_layouts, &shader)
+            });
+            match pipeline {
+                Ok(pipeline) => *pipelines = HashMap::from([(mode, pipeline)]),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        errors
+    }
+
+    /// What the overlay pass draws into: the output itself, with a depth buffer of its own.
+    fn overlay_format(&self) -> pipeline::TargetFormat {
+        pipeline::TargetFormat { sample_count: 1, ..self.target_format }
+    }
+
+    /// Rebuilds every pipeline after a shader file was saved. Where the new shader doesn't
+    /// compile the previous pipeline stays and the error is shown on screen until it does.
+    fn reload_shaders(&mut self) {
+        let mut errors = self.create_pipelines();
+        errors.extend(self.post.reload(&self.device, &mut self.pipeline_cache));
+        errors.extend(self.materials.reload(&self.device, &mut self.pipeline_cache, &[&self.sprite_array_bind_group_layout, &self.camera_bind_group_layout]));
+
+        // pipelines of the shaders as they were before
+        self.pipeline_cache.trim();
+
+        self.set_shader_errors(errors);
+    }
+
+    /// Prints errors and shows them on screen, in place of the ones before.
+    fn set_shader_errors(&mut self, errors: Vec<anyhow::Error>) {
+        // a broken shared module breaks every shader including it the same way
+        let mut messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        messages.sort();
+        messages.dedup();
+
+        if messages.is_empty() && !self.shader_errors.is_empty() {
+            log::info!("shaders reloaded");
+        }
+        for message in &messages {
+            eprintln!("{}", message);
+        }
+
+        self.shader_errors = messages;
+        self.layout_shader_errors();
+    }
+
+    /// Rebuilds the stats overlay, which changes every frame it is shown.
+    fn layout_stats(&mut self) {
+        self.stats.texture_memory = self.texture_memory();
+
+        let (vecs, count) = self.stats.overlay(self.config.width as f32, self.config.height as f32);
+        self.stats_layer = Layer {
+            buffers: vecs.to_buffers(&self.device),
+            batches: blend::batches((0..count).map(|_| Batch::from(blend::BlendMode::Opaque)), 6),
+        };
+    }
+
+    /// Widgets into a group of layers per clip rectangle.
+    fn layout_widgets(&mut self) {
+        let (width, height) = (self.config.width as f32, self.config.height as f32);
+        let alpha = |length: usize| match length {
+            0 => Vec::new(),
+            length => vec![(Batch::from(blend::BlendMode::Alpha), 0..length as u32)],
+        };
+
+        let groups = self.widgets.draw(&self.world);
+        self.widget_groups = groups.into_iter()
+            .filter_map(|group| {
+                // scissor rects count down from the top of the window
+                let scissor = match group.clip {
+                    Some([min_x, min_y, max_x, max_y]) => {
+                        let (left, right) = (min_x.clamp(0.0, width).floor(), max_x.clamp(0.0, width).ceil());
+                        let (top, bottom) = ((height - max_y).clamp(0.0, height).floor(), (height - min_y).clamp(0.0, height).ceil());
+                        if right <= left || bottom <= top {
+                            return None;
+                        }
+                        Some([left as u32, top as u32, (right - left) as u32, (bottom - top) as u32])
+                    },
+                    None => None,
+                };
+                let images = sprite::SpriteVecs::from_quads(&group.images);
+
+                Some(WidgetGroup {
+                    scissor,
+                    shapes: Layer { buffers: group.shapes.to_buffers(&self.device), batches: alpha(group.shapes.indices.len()) },
+                    images: Layer { buffers: images.to_buffers(&self.device), batches: alpha(images.indices.len()) },
+                    text: Layer { buffers: group.text.to_buffers(&self.device), batches: alpha(group.text.indices.len()) },
+                })
+            })
+            .collect();
+    }
+
+    /// This frame's debug shapes into the two debug layers.
+    fn layout_debug(&mut self) {
+        let (world, screen) = self.debug.tessellate();
+
+        for (vecs, layer) in [(world, &mut self.debug_layer), (screen, &mut self.screen_debug_layer)] {
+            let length = vecs.indices.len() as u32;
+            *layer = Layer {
+                buffers: vecs.to_buffers(&self.device),
+                batches: if length > 0 { vec![(Batch::from(debug_draw::BLEND_MODE), 0..length)] } else { Vec::new() },
+            };
+        }
+    }
+
+    /// Everything holding on to textures, added up.
+    fn texture_memory(&self) -> u64 {
+        let offscreen = self.offscreen_views.iter().map(|view| view.target.memory()).sum::<u64>();
+
+        self.loader.memory() + offscreen + self.transient_pool.borrow().memory() + self.post.memory()
+    }
+
+    /// Lays shader_errors out from the top left corner of the screen.
+    fn layout_shader_errors(&mut self) {
+        let size = 12.0;
+        let columns = (self.config.width as f32 / size) as usize;
+
+        let lines = self.shader_errors.iter().flat_map(|message| message.lines());
+        let mut quads = Vec::new();
+        for (i, line) in lines.enumerate() {
+            // the font sheet only has printable ascii
+            let line = line.chars()
+                .map(|c| if c == '\t' { ' ' } else if (' '..='~').contains(&c) { c } else { '?' })
+                .take(columns.saturating_sub(2))
+                .collect::<String>();
+
+            let y = self.config.height as f32 - size * (i as f32 + 1.0);
+            quads.extend(text::character_quads_from_str(&line, vec![size, y, 0.0], size).into_iter().map(|mut quad| {
+                // in front of everything
+                quad.position[2] = 0.9;
+                quad
+            }));
+        }
+
+        self.shader_error_layer = Layer {
+            buffers: text::TextVecs::from_quads(&quads).to_buffers(&self.device),
+            batches: blend::batches(quads.iter().map(|_| Batch::from(blend::BlendMode::Opaque)), 6),
+        };
+    }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) { 
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
@@ -328,8 +745,15 @@ impl State {
             self.camera.width = new_size.width as f32;
             self.camera.height = new_size.height as f32;
 
+            if !self.shader_errors.is_empty() {
+                self.layout_shader_errors();
+            }
+            // scissor rects are from the top of the window
+            self.widgets.changed = true;
+
             self.camera_uniform.update_proj(&self.camera);
             self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+            stats::count_upload(std::mem::size_of::<camera::CameraUniform>() as u64);
         }
     }
 
@@ -350,6 +774,10 @@ impl State {
                 self.input.handle_mouse_move(*position);
                 true
             },
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.input.handle_mouse_wheel(*delta);
+                true
+            },
             _ => false
         }
         /*
@@ -359,11 +787,65 @@ impl State {
     }
 
     fn update(&mut self, dt: time::Duration) {
+        self.stats.frame(dt);
+        self.debug.frame(dt);
+        if let Some(timer) = &self.gpu_timer {
+            let mut timer = timer.borrow_mut();
+            timer.poll(&self.device);
+            self.stats.gpu_times = Some(timer.times().to_vec());
+        }
+
+        if self.input.is_key_pressed(input_general::Key::F3) {
+            self.stats.visible = !self.stats.visible;
+        }
+
+        // the demo's post effect, pushed the first time and toggled after
+        if self.input.is_key_pressed(input_general::Key::F4) {
+            match self.post.effects().first() {
+                Some(vignette) => self.post.set_enabled(0, !vignette.enabled),
+                None => {
+                    let vignette = post::Effect::Vignette(post::Vignette::default());
+                    if let Err(e) = self.post.push(&self.device, &mut self.pipeline_cache, vignette) {
+                        eprintln!("{:?}", e);
+                    }
+                },
+            }
+        }
+
+        if self.input.is_key_pressed(input_general::Key::F12) {
+            match self.screenshot() {
+                Ok(path) => println!("saved {}", path.display()),
+                Err(e) => eprintln!("screenshot failed: {:?}", e),
+            }
+        }
+
+        for ready in self.loader.poll(&self.device, &self.queue) {
+            match ready {
+                loader::Ready::Texture(handle) => {
+                    if handle == self.ui_texture {
+                        self.ui_bind_group = self.loader.texture(handle)
+                            .bind_group(&self.device, &self.ui_texture_bind_group_layout, "ui_texture_bind_group");
+                    }
+                    self.materials.texture_loaded(&self.device, &self.loader, handle);
+                },
+                loader::Ready::TextureArray(handle) => {
+                    if Some(handle) == self.sprite_textures {
+                        self.bind_sprite_textures(handle);
+                    }
+                },
+            }
+        }
+
+        if let Some(watcher) = &mut self.shader_watcher {
+            if !watcher.changed().is_empty() {
+                self.reload_shaders();
+            }
+        }
+
         // display framerate
-        let mut elements: Vec<text::CharacterQuad> = Vec::new();
+        let mut elements: Vec<(text::CharacterQuad, blend::BlendMode)> = Vec::new();
         {
-            let millis = if dt.as_millis() == 0 { 1 } else { dt.as_millis() };
-            let framerate = (1000 / millis) as u32;
+            let framerate = self.stats.fps().round() as u32;
             //let mut framerate_comp = self.world.get::<&mut Framerate>(self.framerate_entity).unwrap();
 
             //let mut framerate_e = self.world.query_mut::<(&Framerate, &UIRenderable, &Vec<text::CharacterQuad>)>().into_iter().next().unwrap();
@@ -387,25 +869,124 @@ impl State {
             }
         }
         
+        if self.shapes_changed {
+            let mut meshes = self.world.query_mut::<&shape::ShapeMesh>()
+                .into_iter()
+                .map(|(_, mesh)| (mesh, shape::BLEND_MODE))
+                .collect::<Vec<_>>();
+
+            blend::sort_for_drawing(&mut meshes, |mesh| mesh.z);
+
+            // a batch each so ui text can be drawn in between them
+            let mut combined = shape::ShapeMesh::default();
+            let mut batches = Vec::new();
+            self.shape_depths.clear();
+            for (mesh, _) in meshes.iter().filter(|(mesh, _)| !mesh.indices.is_empty()) {
+                let start = combined.indices.len() as u32;
+                combined.extend(mesh);
+                batches.push((Batch::from(shape::BLEND_MODE), start..combined.indices.len() as u32));
+                self.shape_depths.push(mesh.z);
+            }
+            self.shape_layer = Layer {
+                buffers: combined.to_buffers(&self.device),
+                batches,
+            };
+
+            self.shapes_changed = false;
+            // the ui batches are split where shapes go between them
+            self.ui_changed = true;
+        }
+
         if self.ui_changed {
 
-            for (id, (ui, quad)) in self.world.query_mut::<(&UIRenderable, &Vec<text::CharacterQuad>)>() {
-                elements.extend(quad);
+            for (_, (_, quad, mode)) in self.world.query_mut::<(&UIRenderable, &Vec<text::CharacterQuad>, Option<&blend::BlendMode>)>() {
+                let mode = mode.copied().unwrap_or_default();
+                elements.extend(quad.iter().map(|q| (*q, mode)));
             }
 
-            self.ui_layer = text::TextVecs::from_quads(&elements).to_buffers(&self.device);
+            blend::sort_for_drawing(&mut elements, |quad| quad.position[2]);
+
+            // a shape on the same z as a quad is drawn after it
+            let shapes_behind = |quad: &text::CharacterQuad, mode: blend::BlendMode| match mode.writes_depth() {
+                true => 0,
+                false => self.shape_depths.partition_point(|z| *z < quad.position[2]),
+            };
+            let batches = blend::batches(elements.iter().map(|(quad, mode)| (Batch::from(*mode), shapes_behind(quad, *mode))), 6);
+
+            let quads = elements.iter().map(|(quad, _)| *quad).collect::<Vec<_>>();
+            self.ui_shapes_behind = batches.iter().map(|((_, behind), _)| *behind).collect();
+            self.ui_layer = Layer {
+                buffers: text::TextVecs::from_quads(&quads).to_buffers(&self.device),
+                batches: batches.into_iter().map(|((batch, _), range)| (batch, range)).collect(),
+            };
 
             self.ui_changed = false;
         }
 
+        self.widgets.update(&mut self.world, &self.input, self.config.height as f32);
+        if self.widgets.changed {
+            self.layout_widgets();
+            self.widgets.changed = false;
+        }
+
+        // spawning or despawning sprites is picked up here, changing one in place needs sprites_changed
+        let sprite_count = self.world.query_mut::<&sprite::SpriteQuad>().into_iter().count()
+            + self.world.query_mut::<&nine_slice::Panel>().into_iter().count();
+        if sprite_count != self.sprite_count {
+            self.sprite_count = sprite_count;
+            self.sprites_changed = true;
+        }
+
+        if self.sprites_changed {
+            let mut quads = self.world.query_mut::<(&sprite::SpriteQuad, Option<&blend::BlendMode>, Option<&material::MaterialId>)>()
+                .into_iter()
+                .map(|(_, (quad, mode, material))| ((*quad, material.copied()), mode.copied().unwrap_or_default()))
+                .collect::<Vec<_>>();
+
+            // panels are drawn as the sprites they are made of
+            for (_, (panel, mode, material)) in self.world.query_mut::<(&nine_slice::Panel, Option<&blend::BlendMode>, Option<&material::MaterialId>)>() {
+                let mode = mode.copied().unwrap_or_default();
+                quads.extend(panel.sprite_quads().map(|quad| ((quad, material.copied()), mode)));
+            }
+
+            blend::sort_for_drawing(&mut quads, |(quad, _)| quad.position[2]);
+
+            let target_format = self.target_format;
+            let batches = blend::batches(quads.iter().map(|((_, material), blend_mode)| Batch {
+                blend_mode: *blend_mode,
+                material: material.map(|material| material::PipelineKey {
+                    material,
+                    vertex_layout: material::VertexLayout::Sprite,
+                    blend_mode: *blend_mode,
+                    target_format,
+                }),
+            }), 6);
+
+            for key in batches.iter().filter_map(|(batch, _)| batch.material) {
+                let base_layouts = [&self.sprite_array_bind_group_layout, &self.camera_bind_group_layout];
+                if let Err(e) = self.materials.prepare(&self.device, &mut self.pipeline_cache, key, &base_layouts) {
+                    eprintln!("{}", e);
+                }
+            }
+
+            self.sprite_layer = Layer {
+                buffers: sprite::SpriteVecs::from_quads(quads.iter().map(|((quad, _), _)| quad)).to_buffers(&self.device),
+                batches,
+            };
+
+            self.sprites_changed = false;
+        }
+
         let speed = 1.0;
         let mut moved = false;
         let (mut x, mut y) = (0.0, 0.0);
 
-        let movement_keys = ( self.input.is_key_down(winit::event::VirtualKeyCode::Left)
-                            , self.input.is_key_down(winit::event::VirtualKeyCode::Right)
-                            , self.input.is_key_down(winit::event::VirtualKeyCode::Up)
-                            , self.input.is_key_down(winit::event::VirtualKeyCode::Down));
+        // the arrow keys belong to a focused widget, sliders move with them
+        let free = self.widgets.focused().is_none();
+        let movement_keys = ( free && self.input.is_key_down(winit::event::VirtualKeyCode::Left)
+                            , free && self.input.is_key_down(winit::event::VirtualKeyCode::Right)
+                            , free && self.input.is_key_down(winit::event::VirtualKeyCode::Up)
+                            , free && self.input.is_key_down(winit::event::VirtualKeyCode::Down));
         
         match movement_keys {
             (true, false, false, false) | (true, false, true, true) => { // Left
@@ -452,8 +1033,21 @@ impl State {
             self.camera.modify_position(x, y);
             self.camera_uniform.update_proj(&self.camera);
             self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+            stats::count_upload(std::mem::size_of::<camera::CameraUniform>() as u64);
+        }
+
+        for offscreen in &mut self.offscreen_views {
+            offscreen.binding.update(&self.queue, &offscreen.camera);
+        }
+
+        if self.stats.visible {
+            self.layout_stats();
         }
 
+        // skips the uploads while there is nothing drawn now or last frame
+        if !self.debug.is_empty() || !self.debug_layer.batches.is_empty() || !self.screen_debug_layer.batches.is_empty() {
+            self.layout_debug();
+        }
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -465,99 +1059,319 @@ impl State {
             label: Some("Render Encoder"),
         });
 
-        {
+        self.encode_frame(&mut encoder, &view, true);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = &self.gpu_timer {
+            timer.borrow_mut().submitted();
+        }
+        output.present();
+
+
+        Ok(())
+
+    }
+
+    /// Builds this frame's render graph drawing into output and records it, with the
+    /// passes timed on the GPU if timed is set and the adapter can.
+    fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView, timed: bool) {
+        let mut graph = graph::RenderGraph::new();
+        self.add_scene_passes(&mut graph, output);
+
+        let pool = &mut self.transient_pool.borrow_mut();
+        let recorded = match self.gpu_timer.as_ref().filter(|_| timed) {
+            Some(timer) => graph.execute_timed(&self.device, pool, encoder, &mut timer.borrow_mut()),
+            None => graph.execute(&self.device, pool, encoder),
+        };
+        if let Err(e) = recorded {
+            eprintln!("Failed to record frame: {}", e);
+        }
+    }
+
+    /// Offscreen cameras into their targets, then the main camera's view of the scene,
+    /// through the post chain when it has anything enabled.
+    fn add_scene_passes<'a>(&'a self, graph: &mut graph::RenderGraph<'a>, output: &'a wgpu::TextureView) {
+        let mut insets = Vec::new();
+        for (i, offscreen) in self.offscreen_views.iter().enumerate() {
+            let target = graph.import_texture(&format!("offscreen_{}", i), &offscreen.target.color.view);
+            if offscreen.inset.is_some() {
+                insets.push(target);
+            }
+
+            graph.add_pass("Offscreen Pass", &[], &[target], move |_, encoder| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Offscreen Pass"),
+                    color_attachments: &[Some(offscreen.target.color_attachment(offscreen.clear_color))],
+                    depth_stencil_attachment: offscreen.target.depth_attachment(),
+                });
+
+                self.draw_scene(&mut render_pass, &offscreen.binding.bind_group);
+            });
+        }
+
+        let (width, height) = (self.config.width, self.config.height);
+        let pipeline::TargetFormat { color, depth, sample_count } = self.target_format;
+
+        let output = graph.import_texture("output", output);
+        let scene = if self.post.is_active() {
+            graph.create_texture("scene", graph::TextureDesc::color(width, height, color))
+        } else {
+            output
+        };
+        let multisampled = (sample_count > 1)
+            .then(|| graph.create_texture("scene_multisampled", graph::TextureDesc::multisampled(width, height, color, sample_count)));
+        let depth = depth
+            .map(|format| graph.create_texture("scene_depth", graph::TextureDesc::depth(width, height, format, sample_count)));
+
+        let writes = [Some(scene), multisampled, depth].into_iter().flatten().collect::<Vec<_>>();
+        graph.add_pass("Render Pass", &[], &writes, move |ctx, encoder| {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(
-                                wgpu::Color {
-                                    r: 0.1,
-                                    g: 0.2,
-                                    b: 0.3,
-                                    a: 1.0,
-                                }
-                            ),
-                            store: true,
-                        }
-                    }
-                )],
-                depth_stencil_attachment: None,
+                    Some(texture::color_attachment(ctx.view(scene), multisampled.map(|id| ctx.view(id)), CLEAR_COLOR)),
+                ],
+                depth_stencil_attachment: depth.map(|id| wgpu::RenderPassDepthStencilAttachment {
+                    view: ctx.view(id),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            self.draw_scene(&mut render_pass, &self.camera_bind_group);
+        });
+
+        if scene != output {
+            self.post.add_passes(graph, scene, output, width, height);
+        }
+
+        // overlays go on top of the finished frame so post processing leaves them alone
+        let overlay_depth = self.overlay_format().depth
+            .map(|format| graph.create_texture("overlay_depth", graph::TextureDesc::depth(width, height, format, 1)));
+        let writes = [Some(output), overlay_depth].into_iter().flatten().collect::<Vec<_>>();
+        graph.add_pass("Overlay Pass", &insets, &writes, move |ctx, encoder| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: ctx.view(output),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: overlay_depth.map(|id| wgpu::RenderPassDepthStencilAttachment {
+                    view: ctx.view(id),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
             });
 
+            // below the rest of the overlays, skipped while the window is too small to fit them
+            if let Some(pipeline) = self.offscreen_pipelines.get(&blend::BlendMode::Alpha) {
+                for offscreen in &self.offscreen_views {
+                    let Some([x, y, inset_width, inset_height]) = offscreen.inset else { continue };
+                    let top = height as f32 - y - inset_height;
+                    if x < 0.0 || y < 0.0 || top < 0.0 || x + inset_width > width as f32 {
+                        continue;
+                    }
+
+                    render_pass.set_viewport(x, top, inset_width, inset_height, 0.0, 1.0);
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.set_bind_group(0, &offscreen.bind_group, &[]);
+                    // unused by the shader, but the ui layout it shares has a camera
+                    render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                    render_pass.draw(0..3, 0..1);
+                    stats::count_draw(3);
+                }
+                render_pass.set_viewport(0.0, 0.0, width as f32, height as f32, 0.0, 1.0);
+            }
+
+            for (pipelines, layer) in [(&self.debug_pipelines, &self.debug_layer), (&self.screen_debug_pipelines, &self.screen_debug_layer)] {
+                draw_layer(
+                    &mut render_pass,
+                    pipelines,
+                    &self.materials,
+                    layer,
+                    true,
+                    &self.ui_bind_group,
+                    &self.camera_bind_group,
+                );
+            }
 
-            render_pass.set_pipeline(&self.ui_render_pipeline);
-            render_pass.set_vertex_buffer(0, self.ui_layer.vertices.slice(..));
-            render_pass.set_index_buffer(self.ui_layer.indices.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.set_bind_group(0, &self.ui_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.draw_indexed(0..self.ui_layer.length, 0, 0..1);
+            for group in &self.widget_groups {
+                if let Some([x, y, width, height]) = group.scissor {
+                    render_pass.set_scissor_rect(x, y, width, height);
+                }
 
+                draw_layer(&mut render_pass, &self.screen_shape_pipelines, &self.materials, &group.shapes, true, &self.ui_bind_group, &self.camera_bind_group);
+                if let Some(sprite_bind_group) = &self.sprite_array {
+                    draw_layer(&mut render_pass, &self.screen_sprite_pipelines, &self.materials, &group.images, true, sprite_bind_group, &self.camera_bind_group);
+                }
+                draw_layer(&mut render_pass, &self.screen_debug_pipelines, &self.materials, &group.text, true, &self.ui_bind_group, &self.camera_bind_group);
 
-        }
+                if group.scissor.is_some() {
+                    render_pass.set_scissor_rect(0, 0, width, height);
+                }
+            }
 
+            draw_layer(
+                &mut render_pass,
+                &self.screen_ui_pipelines,
+                &self.materials,
+                &self.shader_error_layer,
+                false,
+                &self.ui_bind_group,
+                &self.camera_bind_group,
+            );
+            if self.stats.visible {
+                draw_layer(
+                    &mut render_pass,
+                    &self.screen_ui_pipelines,
+                    &self.materials,
+                    &self.stats_layer,
+                    false,
+                    &self.ui_bind_group,
+                    &self.camera_bind_group,
+                );
+            }
+        });
+    }
+
+    /// Renders the frame again into an offscreen texture and saves it as a png in the working directory.
+    fn screenshot(&self) -> anyhow::Result<std::path::PathBuf> {
+        let target = texture::Texture::create_render_target(&self.device, self.config.width, self.config.height, self.config.format, "Screenshot Target");
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor{
+            label: Some("Screenshot Encoder"),
+        });
+        // not timed, the timer expects every frame it times to be submitted through render
+        self.encode_frame(&mut encoder, &target.view, false);
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
 
+        let readback = target.read_pixels(&self.device, &self.queue, None)?;
+        self.device.poll(wgpu::Maintain::Wait);
+        let img = pollster::block_on(readback)?;
 
-        Ok(())
+        let seconds = time::SystemTime::now().duration_since(time::UNIX_EPOCH)?.as_secs();
+        let path = std::path::PathBuf::from(format!("screenshot_{}.png", seconds));
+        img.save(&path)?;
+
+        Ok(path)
+    }
+
+    fn draw_scene<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        // opaque first in any order, the depth test sorts them out
+        self.draw_sprites(render_pass, camera_bind_group, false);
+        draw_layer(render_pass, &self.ui_pipelines, &self.materials, &self.ui_layer, false, &self.ui_bind_group, camera_bind_group);
+
+        // then the transparent batches, each already sorted back to front. Sprites are sorted on
+        // their own, so a transparent sprite and transparent text overlapping each other can still
+        // be out of order. Text and shapes take turns so they share one order
+        self.draw_sprites(render_pass, camera_bind_group, true);
+
+        let mut ui_batches = self.ui_layer.batches.iter().zip(&self.ui_shapes_behind).peekable();
+        for shape in 0..=self.shape_layer.batches.len() {
+            let mut text = Vec::new();
+            while let Some(((batch, range), _)) = ui_batches.next_if(|(_, behind)| **behind <= shape) {
+                if !batch.blend_mode.writes_depth() {
+                    text.push((batch, range));
+                }
+            }
+            if !text.is_empty() {
+                bind_layer(render_pass, &self.ui_layer, &self.ui_bind_group, camera_bind_group);
+                draw_batches(render_pass, &self.ui_pipelines, &self.materials, text);
+            }
+
+            if let Some((batch, range)) = self.shape_layer.batches.get(shape) {
+                bind_layer(render_pass, &self.shape_layer, &self.ui_bind_group, camera_bind_group);
+                draw_batches(render_pass, &self.shape_pipelines, &self.materials, [(batch, range)]);
+            }
+        }
+    }
 
+    fn draw_sprites<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup, transparent: bool) {
+        // every sprite, whichever array layer it uses, in one draw per blend mode
+        if let Some(sprite_bind_group) = &self.sprite_array {
+            draw_layer(render_pass, &self.sprite_pipelines, &self.materials, &self.sprite_layer, transparent, sprite_bind_group, camera_bind_group);
+        }
     }
 
 }
 
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
-    device.create_render_pipeline(
-        &wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: vertex_layouts,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[
-                    Some(wgpu::ColorTargetState {
-                        format: color_format,
-                        blend: Some(wgpu::BlendState {
-                            alpha: wgpu::BlendComponent::OVER,
-                            color: wgpu::BlendComponent::OVER,
-                        }),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })
-                ],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
+/// Draws either the opaque or the transparent batches of a layer.
+fn draw_layer<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pipelines: &'a HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    materials: &'a material::Materials,
+    layer: &'a Layer,
+    transparent: bool,
+    texture_bind_group: &'a wgpu::BindGroup,
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    let mut batches = layer.batches.iter()
+        .filter(|(batch, _)| batch.blend_mode.writes_depth() != transparent)
+        .peekable();
+
+    if layer.buffers.length == 0 || batches.peek().is_none() {
+        return;
+    }
+
+    bind_layer(render_pass, layer, texture_bind_group, camera_bind_group);
+    draw_batches(render_pass, pipelines, materials, batches.map(|(batch, indices)| (batch, indices)));
+}
+
+fn bind_layer<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    layer: &'a Layer,
+    texture_bind_group: &'a wgpu::BindGroup,
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    render_pass.set_vertex_buffer(0, layer.buffers.vertices.slice(..));
+    render_pass.set_index_buffer(layer.buffers.indices.slice(..), wgpu::IndexFormat::Uint32);
+    render_pass.set_bind_group(0, texture_bind_group, &[]);
+    render_pass.set_bind_group(1, camera_bind_group, &[]);
+}
+
+/// Draws batches of whichever layer bind_layer was last given.
+fn draw_batches<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pipelines: &'a HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>,
+    materials: &'a material::Materials,
+    batches: impl IntoIterator<Item = (&'a Batch, &'a Range<u32>)>,
+) {
+    for (batch, indices) in batches {
+        match &batch.material {
+            Some(key) => {
+                let Some(pipeline) = materials.pipeline(key) else { continue };
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(2, materials.bind_group(key.material), &[]);
             },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+            None => {
+                let Some(pipeline) = pipelines.get(&batch.blend_mode) else { continue };
+                render_pass.set_pipeline(pipeline);
             },
-            multiview: None,
         }
-    )
-}
\ No newline at end of file
+        render_pass.draw_indexed(indices.clone(), 0, 0..1);
+        stats::count_draw(indices.len() as u32);
+    }
+}
+
+/// The highest sample count up to requested that every format can be rendered with.
+fn supported_sample_count(adapter: &wgpu::Adapter, device: &wgpu::Device, formats: &[wgpu::TextureFormat], requested: u32) -> u32 {
+    let adapter_specific = device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+    [16, 8, 4, 2].into_iter()
+        .filter(|count| *count <= requested)
+        // without adapter specific features WebGPU only guarantees 4
+        .filter(|count| adapter_specific || *count == 4)
+        .find(|count| formats.iter().all(|format| {
+            adapter.get_texture_format_features(*format).flags.sample_count_supported(*count)
+        }))
+        .unwrap_or(1)
+}
@@ -0,0 +1,507 @@
+/*
+Custom materials for sprites.
+
+A Material is the fragment half of a shader plus the parameters and textures
+it declares. The vertex half comes from the prelude of the vertex layout it is
+drawn with (src/shader/sprite.wgsl for sprites), which also gives the
+material's source:
+ - VertexOutput with tex_coords and layer
+ - base_color(in), the texel the sprite would have drawn without a material
+ - sprite_sample(in, uv) and sprite_texel_size() for sampling around it
+ - `material`, a uniform with each declared parameter as a field of the same name
+ - t_<name> and s_<name> for each declared texture
+
+so a material only has to define `fs_main(in: VertexOutput) -> @location(0) vec4<f32>`.
+Parameters and textures are bind group 2, groups 0 and 1 are the same as the
+vertex layout's built in pipeline. The source goes through shader::Preprocessor
+so it can #include the shared modules. See src/material/ for examples.
+
+Put a MaterialId on a sprite entity to draw it with that material. Entities
+sharing a material share its parameters, so a flash on a single sprite wants
+a material of its own. Pipelines are made the first time each combination of
+material, vertex layout, blend mode and target format is drawn, and kept. A
+material that doesn't compile is reported then and its sprites aren't drawn.
+*/
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::blend;
+use crate::loader;
+use crate::pipeline;
+use crate::shader;
+use crate::sprite;
+use crate::stats;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Param {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec4([f32; 4]),
+}
+
+impl Param {
+    /// Every parameter takes a full vec4 slot in the uniform, whatever its type.
+    fn slot(self) -> [f32; 4] {
+        match self {
+            Param::Float(x) => [x, 0.0, 0.0, 0.0],
+            Param::Vec2([x, y]) => [x, y, 0.0, 0.0],
+            Param::Vec4(v) => v,
+        }
+    }
+
+    /// The struct fields declaring this parameter, padded out to the slot.
+    fn fields(self, name: &str) -> String {
+        match self {
+            Param::Float(_) => format!(
+                "    {name}: f32,\n    _{name}_pad0: f32,\n    _{name}_pad1: f32,\n    _{name}_pad2: f32,\n"
+            ),
+            Param::Vec2(_) => format!("    {name}: vec2<f32>,\n    _{name}_pad: vec2<f32>,\n"),
+            Param::Vec4(_) => format!("    {name}: vec4<f32>,\n"),
+        }
+    }
+
+    fn same_type(self, other: Param) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Text(String),
+    // a built in shader file, read again on every reload so saving it is picked up
+    File(&'static str),
+}
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    name: String,
+    source: Source,
+    params: Vec<(String, Param)>,
+    textures: Vec<(String, loader::TextureHandle)>,
+}
+
+impl Material {
+    /// source is the material's fragment stage, see the top of this file for what it can use.
+    pub fn new(name: &str, source: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            source: Source::Text(source.into()),
+            params: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    fn from_file(name: &str, file: &'static str) -> Self {
+        Self {
+            source: Source::File(file),
+            ..Self::new(name, "")
+        }
+    }
+
+    /// Declares a parameter with its starting value, Materials::set_param changes it later.
+    pub fn param(mut self, name: &str, value: Param) -> Self {
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    /// Declares a texture, the material uses the loader's placeholder until it arrives.
+    pub fn texture(mut self, name: &str, texture: loader::TextureHandle) -> Self {
+        self.textures.push((name.to_string(), texture));
+        self
+    }
+
+    /// Set amount above 0 to flash.
+    pub fn flash(color: [f32; 4]) -> Self {
+        Self::from_file("flash", "material/flash.wgsl")
+            .param("color", Param::Vec4(color))
+            .param("amount", Param::Float(0.0))
+    }
+
+    /// Raise threshold from 0 to 1 to dissolve.
+    pub fn dissolve(noise: loader::TextureHandle, edge_color: [f32; 4]) -> Self {
+        Self::from_file("dissolve", "material/dissolve.wgsl")
+            .param("threshold", Param::Float(0.0))
+            .param("edge_width", Param::Float(0.05))
+            .param("edge_color", Param::Vec4(edge_color))
+            .texture("noise", noise)
+    }
+
+    /// Needs a blending mode, the line is partly transparent at its edges.
+    pub fn outline(color: [f32; 4], width: f32) -> Self {
+        Self::from_file("outline", "material/outline.wgsl")
+            .param("color", Param::Vec4(color))
+            .param("width", Param::Float(width))
+    }
+
+    fn validate(&self) -> Result<()> {
+        let names = self.params.iter().map(|(name, _)| name).chain(self.textures.iter().map(|(name, _)| name));
+        let mut seen = Vec::new();
+
+        for name in names {
+            let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            ensure!(valid, "material {}: {:?} is not a valid WGSL identifier", self.name, name);
+            ensure!(!seen.contains(&name), "material {}: {} is declared twice", self.name, name);
+            seen.push(name);
+        }
+
+        Ok(())
+    }
+
+    fn uniform_data(&self) -> Vec<[f32; 4]> {
+        if self.params.is_empty() {
+            // a uniform can't be empty
+            return vec![[0.0; 4]];
+        }
+
+        self.params.iter().map(|(_, value)| value.slot()).collect()
+    }
+
+    /// The bind group 2 declarations for this material's parameters and textures.
+    fn bindings(&self) -> String {
+        let mut fields = self.params.iter()
+            .map(|(name, value)| value.fields(name))
+            .collect::<String>();
+        if fields.is_empty() {
+            fields = "    _unused: vec4<f32>,\n".to_string();
+        }
+
+        let mut wgsl = format!(
+            "struct MaterialParams {{\n{}}}\n\n@group(2)@binding(0)\nvar<uniform> material: MaterialParams;\n",
+            fields,
+        );
+
+        for (i, (name, _)) in self.textures.iter().enumerate() {
+            wgsl += &format!(
+                "@group(2)@binding({})\nvar t_{}: texture_2d<f32>;\n@group(2)@binding({})\nvar s_{}: sampler;\n",
+                1 + 2 * i, name, 2 + 2 * i, name,
+            );
+        }
+
+        wgsl
+    }
+
+    /// The whole shader drawing this material with vertex_layout.
+    fn shader(&self, vertex_layout: VertexLayout) -> Result<shader::Shader> {
+        // the material's own source is included rather than pasted in, so errors point at its lines
+        let (module, preprocessor) = match &self.source {
+            Source::File(file) => (file.to_string(), shader::Preprocessor::new()),
+            Source::Text(text) => {
+                let module = format!("material/{}.wgsl", self.name);
+                let preprocessor = shader::Preprocessor::new().module(&module, text);
+                (module, preprocessor)
+            },
+        };
+        let source = format!(
+            "#include \"{}\"\n{}\n#include \"{}\"\n",
+            vertex_layout.prelude(),
+            self.bindings(),
+            module,
+        );
+
+        preprocessor.process(&self.name, &source)
+    }
+}
+
+/// What materials can be drawn with. Each has its own vertex stage prelude.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    Sprite,
+}
+
+impl VertexLayout {
+    fn desc(self) -> wgpu::VertexBufferLayout<'static> {
+        match self {
+            VertexLayout::Sprite => sprite::SpriteVertex::desc(),
+        }
+    }
+
+    fn prelude(self) -> &'static str {
+        match self {
+            VertexLayout::Sprite => "shader/sprite.wgsl",
+        }
+    }
+}
+
+/// A sprite component, index into Materials.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub material: MaterialId,
+    pub vertex_layout: VertexLayout,
+    pub blend_mode: blend::BlendMode,
+    pub target_format: pipeline::TargetFormat,
+}
+
+struct Entry {
+    material: Material,
+    params: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // kept so the pipeline cache sees the same layout each time
+    pipeline_layouts: HashMap<VertexLayout, wgpu::PipelineLayout>,
+}
+
+#[derive(Default)]
+pub struct Materials {
+    entries: Vec<Entry>,
+    pipelines: HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>,
+}
+
+impl Materials {
+    pub fn add(&mut self, device: &wgpu::Device, loader: &loader::AssetLoader, material: Material) -> Result<MaterialId> {
+        material.validate()?;
+
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&material.name),
+            contents: bytemuck::cast_slice(&material.uniform_data()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        for i in 0..material.textures.len() as u32 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + 2 * i,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + 2 * i,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material_bind_group_layout"),
+            entries: &entries,
+        });
+        let bind_group = create_bind_group(device, loader, &material, &params, &bind_group_layout);
+
+        self.entries.push(Entry { material, params, bind_group_layout, bind_group, pipeline_layouts: HashMap::new() });
+        Ok(MaterialId(self.entries.len() - 1))
+    }
+
+    pub fn set_param(&mut self, queue: &wgpu::Queue, id: MaterialId, name: &str, value: Param) -> Result<()> {
+        let entry = &mut self.entries[id.0];
+        let material_name = &entry.material.name;
+
+        let (index, (_, param)) = entry.material.params.iter_mut()
+            .enumerate()
+            .find(|(_, (param_name, _))| param_name == name)
+            .with_context(|| format!("material {} has no parameter {}", material_name, name))?;
+        ensure!(param.same_type(value), "material {}: {} is a {:?}, not a {:?}", material_name, name, param, value);
+
+        *param = value;
+        let offset = (index * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress;
+        queue.write_buffer(&entry.params, offset, bytemuck::cast_slice(&[value.slot()]));
+        stats::count_upload(std::mem::size_of::<[f32; 4]>() as u64);
+
+        Ok(())
+    }
+
+    /// Call with textures the loader has finished, materials using them swap off the placeholder.
+    pub fn texture_loaded(&mut self, device: &wgpu::Device, loader: &loader::AssetLoader, handle: loader::TextureHandle) {
+        for entry in &mut self.entries {
+            if entry.material.textures.iter().any(|(_, texture)| *texture == handle) {
+                entry.bind_group = create_bind_group(device, loader, &entry.material, &entry.params, &entry.bind_group_layout);
+            }
+        }
+    }
+
+    /// Makes the pipeline for key if it isn't cached yet. base_layouts are bind groups 0 and 1 of the vertex layout.
+    pub fn prepare(&mut self, device: &wgpu::Device, cache: &mut pipeline::PipelineCache, key: PipelineKey, base_layouts: &[&wgpu::BindGroupLayout]) -> Result<()> {
+        if !self.pipelines.contains_key(&key) {
+            let pipeline = self.create_pipeline(device, cache, key, base_layouts)?;
+            self.pipelines.insert(key, pipeline);
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds every cached pipeline, keeping the old one for any that fail.
+    pub fn reload(&mut self, device: &wgpu::Device, cache: &mut pipeline::PipelineCache, base_layouts: &[&wgpu::BindGroupLayout]) -> Vec<Error> {
+        let keys = self.pipelines.keys().copied().collect::<Vec<_>>();
+        let mut errors = Vec::new();
+
+        for key in keys {
+            let pipeline = self.create_pipeline(device, cache, key, base_layouts);
+            if let Err(e) = pipeline.map(|pipeline| self.pipelines.insert(key, pipeline)) {
+                errors.push(e);
+            }
+        }
+
+        errors
+    }
+
+    fn create_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut pipeline::PipelineCache,
+        key: PipelineKey,
+        base_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        let entry = &mut self.entries[key.material.0];
+        let shader = entry.material.shader(key.vertex_layout)?;
+
+        let bind_group_layout = &entry.bind_group_layout;
+        let layout = entry.pipeline_layouts.entry(key.vertex_layout).or_insert_with(|| {
+            let mut bind_group_layouts = base_layouts.to_vec();
+            bind_group_layouts.push(bind_group_layout);
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Material Pipeline Layout"),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            })
+        });
+
+        cache.get(
+            device,
+            layout,
+            key.target_format,
+            key.blend_mode,
+            &[key.vertex_layout.desc()],
+            &shader,
+        )
+    }
+
+    pub fn pipeline(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(key).map(|pipeline| &**pipeline)
+    }
+
+    pub fn bind_group(&self, id: MaterialId) -> &wgpu::BindGroup {
+        &self.entries[id.0].bind_group
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    loader: &loader::AssetLoader,
+    material: &Material,
+    params: &wgpu::Buffer,
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 0,
+        resource: params.as_entire_binding(),
+    }];
+    for (i, (_, handle)) in material.textures.iter().enumerate() {
+        let texture = loader.texture(*handle);
+        entries.push(wgpu::BindGroupEntry {
+            binding: 1 + 2 * i as u32,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding: 2 + 2 * i as u32,
+            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+        });
+    }
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("material_bind_group"),
+        layout,
+        entries: &entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_take_a_vec4_slot_each() {
+        let material = Material::new("test", "")
+            .param("amount", Param::Float(0.5))
+            .param("offset", Param::Vec2([1.0, 2.0]))
+            .param("color", Param::Vec4([0.1, 0.2, 0.3, 0.4]));
+
+        assert_eq!(material.uniform_data(), [[0.5, 0.0, 0.0, 0.0], [1.0, 2.0, 0.0, 0.0], [0.1, 0.2, 0.3, 0.4]]);
+        assert_eq!(Material::new("empty", "").uniform_data(), [[0.0; 4]]);
+    }
+
+    #[test]
+    fn bindings_declare_params_then_textures() {
+        let material = Material::new("test", "")
+            .param("amount", Param::Float(0.5))
+            .param("offset", Param::Vec2([1.0, 2.0]))
+            .texture("noise", loader::TextureHandle(0))
+            .texture("mask", loader::TextureHandle(1));
+
+        assert_eq!(material.bindings(), "\
+struct MaterialParams {
+    amount: f32,
+    _amount_pad0: f32,
+    _amount_pad1: f32,
+    _amount_pad2: f32,
+    offset: vec2<f32>,
+    _offset_pad: vec2<f32>,
+}
+
+@group(2)@binding(0)
+var<uniform> material: MaterialParams;
+@group(2)@binding(1)
+var t_noise: texture_2d<f32>;
+@group(2)@binding(2)
+var s_noise: sampler;
+@group(2)@binding(3)
+var t_mask: texture_2d<f32>;
+@group(2)@binding(4)
+var s_mask: sampler;
+");
+
+        // a uniform struct can't be empty
+        assert!(Material::new("empty", "").bindings().starts_with("struct MaterialParams {\n    _unused: vec4<f32>,\n}"));
+    }
+
+    #[test]
+    fn validate_names() {
+        let error = |material: Material| material.validate().unwrap_err().to_string();
+
+        assert!(Material::new("ok", "").param("a_1", Param::Float(0.0)).texture("b", loader::TextureHandle(0)).validate().is_ok());
+        assert_eq!(error(Material::new("m", "").param("1a", Param::Float(0.0))), "material m: \"1a\" is not a valid WGSL identifier");
+        assert_eq!(error(Material::new("m", "").param("", Param::Float(0.0))), "material m: \"\" is not a valid WGSL identifier");
+        assert_eq!(error(Material::new("m", "").param("a-b", Param::Float(0.0))), "material m: \"a-b\" is not a valid WGSL identifier");
+        // params and textures share the bind group's names
+        assert_eq!(
+            error(Material::new("m", "").param("noise", Param::Float(0.0)).texture("noise", loader::TextureHandle(0))),
+            "material m: noise is declared twice",
+        );
+    }
+
+    #[test]
+    fn built_in_materials_compile() {
+        for material in [
+            Material::flash([1.0; 4]),
+            Material::dissolve(loader::TextureHandle(0), [1.0, 0.5, 0.0, 1.0]),
+            Material::outline([0.0, 0.0, 0.0, 1.0], 2.0),
+            Material::new("inline", "@fragment\nfn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {\n    return base_color(in) * material.tint;\n}")
+                .param("tint", Param::Vec4([1.0; 4])),
+        ] {
+            let shader = material.shader(VertexLayout::Sprite).unwrap();
+            if let Err(e) = shader.check() {
+                panic!("{}: {:?}", material.name, e);
+            }
+        }
+    }
+}
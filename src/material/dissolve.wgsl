@@ -0,0 +1,16 @@
+// Burns the sprite away where a noise texture is below threshold, with a glowing edge.
+// params: threshold (0 shows everything, 1 nothing), edge_width, edge_color (vec4)
+// textures: noise, sampled with the sprite's own uvs
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = base_color(in);
+    let noise = textureSample(t_noise, s_noise, in.tex_coords).r;
+
+    if (color.a == 0.0 || noise < material.threshold) {
+        discard;
+    }
+
+    let edge = 1.0 - smoothstep(0.0, material.edge_width, noise - material.threshold);
+    return vec4(mix(color.rgb, material.edge_color.rgb, edge * material.edge_color.a), color.a);
+}
@@ -0,0 +1,13 @@
+// Pulls the sprite towards a solid colour, for hits and pickups.
+// params: color (vec4), amount (0 is the plain sprite, 1 the solid colour)
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = base_color(in);
+
+    if (color.a == 0.0) {
+        discard;
+    }
+
+    return vec4(mix(color.rgb, material.color.rgb, material.amount), color.a);
+}
@@ -0,0 +1,25 @@
+// Draws a coloured line around the opaque parts of the sprite. The line is only
+// drawn inside the quad, so the sprite needs some transparent padding.
+// params: color (vec4), width (in texels)
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = base_color(in);
+    let offset = sprite_texel_size() * material.width;
+
+    var around = sprite_sample(in, in.tex_coords + vec2(offset.x, 0.0)).a;
+    around = max(around, sprite_sample(in, in.tex_coords - vec2(offset.x, 0.0)).a);
+    around = max(around, sprite_sample(in, in.tex_coords + vec2(0.0, offset.y)).a);
+    around = max(around, sprite_sample(in, in.tex_coords - vec2(0.0, offset.y)).a);
+
+    if (color.a > 0.0) {
+        return color;
+    }
+
+    let alpha = around * material.color.a;
+    if (alpha == 0.0) {
+        discard;
+    }
+
+    return vec4(material.color.rgb, alpha);
+}
@@ -0,0 +1,217 @@
+/*
+Mip chain generation, either by rendering each level from the one above it
+with a linear filtered blit, or by resizing on the CPU before upload.
+
+sRGB images are filtered in linear space either way, the GPU by sampling and
+rendering through sRGB views and the CPU by converting to linear floats first.
+The blit pipeline and sampler are made once per device and format on each thread.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::*;
+use image::imageops::FilterType;
+
+use crate::pipeline;
+use crate::shader;
+use crate::texture;
+
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Successively halved copies of the image, not including the image itself.
+pub fn generate_cpu(img: &image::RgbaImage, mip_level_count: u32, color_space: texture::ColorSpace) -> Vec<image::RgbaImage> {
+    if color_space == texture::ColorSpace::Linear {
+        let mut levels: Vec<image::RgbaImage> = Vec::new();
+        for _ in 1..mip_level_count {
+            let prev = levels.last().unwrap_or(img);
+            levels.push(image::imageops::resize(prev, (prev.width() / 2).max(1), (prev.height() / 2).max(1), FilterType::Triangle));
+        }
+        return levels;
+    }
+
+    // each level is made from the linear one above it, so rounding doesn't build up
+    let mut linear = image::Rgba32FImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0.map(|channel| channel as f32 / 255.0);
+        image::Rgba([texture::srgb_to_linear(r), texture::srgb_to_linear(g), texture::srgb_to_linear(b), a])
+    });
+
+    let mut levels = Vec::new();
+    for _ in 1..mip_level_count {
+        linear = image::imageops::resize(&linear, (linear.width() / 2).max(1), (linear.height() / 2).max(1), FilterType::Triangle);
+        levels.push(image::RgbaImage::from_fn(linear.width(), linear.height(), |x, y| {
+            let [r, g, b, a] = linear.get_pixel(x, y).0;
+            let [r, g, b] = [r, g, b].map(texture::linear_to_srgb);
+            image::Rgba([r, g, b, a].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8))
+        }));
+    }
+
+    levels
+}
+
+struct Blitter {
+    device: wgpu::Id<wgpu::Device>,
+    format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+thread_local! {
+    // textures are uploaded from the thread that owns the device, so one list is enough
+    static BLITTERS: RefCell<Vec<Rc<Blitter>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn blitter(device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Rc<Blitter>> {
+    let cached = BLITTERS.with(|blitters| {
+        blitters.borrow().iter().find(|blitter| blitter.device == device.global_id() && blitter.format == format).cloned()
+    });
+    if let Some(blitter) = cached {
+        return Ok(blitter);
+    }
+
+    let shader = shader::Preprocessor::new().compile_file("blit.wgsl")?;
+    let pipeline = pipeline::validated(device, "mipmap pipeline", || {
+        let shader = device.create_shader_module(shader.descriptor());
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    })?;
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Mipmap Sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    let blitter = Rc::new(Blitter { device: device.global_id(), format, pipeline, sampler });
+    BLITTERS.with(|blitters| blitters.borrow_mut().push(blitter.clone()));
+    Ok(blitter)
+}
+
+/// Fills mip levels 1.. of a texture from level 0, the texture needs RENDER_ATTACHMENT usage.
+pub fn generate_gpu(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    mip_level_count: u32,
+) -> Result<()> {
+    let blitter = blitter(device, format)?;
+    let bind_group_layout = blitter.pipeline.get_bind_group_layout(0);
+
+    let views = (0..mip_level_count)
+        .map(|mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip"),
+                format: None,
+                dimension: None,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: 0,
+                array_layer_count: None,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+
+    for target in 1..mip_level_count as usize {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[target - 1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&blitter.sampler),
+                },
+            ],
+            label: None,
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &views[target],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&blitter.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    queue.submit(std::iter::once(encoder.finish()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_counts() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(0, 0), 1);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(257, 3), 9);
+        assert_eq!(mip_level_count(3, 300), 9);
+    }
+
+    #[test]
+    fn cpu_level_sizes() {
+        let img = image::RgbaImage::new(10, 3);
+        let sizes = generate_cpu(&img, mip_level_count(10, 3), texture::ColorSpace::Linear)
+            .iter()
+            .map(|level| level.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(5, 1), (2, 1), (1, 1)]);
+
+        assert!(generate_cpu(&img, 1, texture::ColorSpace::Srgb).is_empty());
+    }
+
+    #[test]
+    fn cpu_filters_srgb_in_linear() {
+        let img = image::RgbaImage::from_fn(2, 2, |x, _| if x == 0 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) });
+
+        // half way between black and white is 0.5 linear, which is 188 in sRGB
+        let srgb = generate_cpu(&img, 2, texture::ColorSpace::Srgb);
+        assert_eq!(srgb[0].get_pixel(0, 0).0, [188, 188, 188, 255]);
+
+        let linear = generate_cpu(&img, 2, texture::ColorSpace::Linear);
+        assert!((127..=128).contains(&linear[0].get_pixel(0, 0).0[0]));
+    }
+}
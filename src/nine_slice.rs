@@ -0,0 +1,248 @@
+/*
+Nine-slice panels: a texture region cut into a 3x3 grid by four insets, so it
+can be drawn at any size with its corners left as they are. The edges and the
+centre either stretch to fill the space between the corners or repeat at their
+original size, with the last repeat cut short.
+
+Every piece is an ordinary quad, NineSlice::vecs gives them as TextVecs for
+whatever texture is bound and a Panel component draws them with the sprites.
+*/
+
+use crate::sprite;
+use crate::text;
+use crate::texture;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Stretch,
+    Tile,
+}
+
+/// Widths of the border in texels of the region.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Insets {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl Insets {
+    pub fn uniform(inset: f32) -> Self {
+        Self { left: inset, right: inset, top: inset, bottom: inset }
+    }
+}
+
+/// One quad of a panel, position is its top left corner like CharacterQuad.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Piece {
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    pub uv: texture::UvRect,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NineSlice {
+    pub uv: texture::UvRect,
+    /// Size of the region in texels, what the insets are measured against.
+    pub texels: [f32; 2],
+    pub insets: Insets,
+    pub edges: Mode,
+    pub centre: Mode,
+    /// World units per texel, for the border and the size of tiles.
+    pub scale: f32,
+}
+
+impl NineSlice {
+    /// Stretched edges and centre at one unit per texel.
+    pub fn new(uv: texture::UvRect, texels: [f32; 2], insets: Insets) -> Self {
+        Self { uv, texels, insets, edges: Mode::Stretch, centre: Mode::Stretch, scale: 1.0 }
+    }
+
+    /// From a pixel rectangle inside a texture of the given size.
+    pub fn from_pixels(x: u32, y: u32, width: u32, height: u32, texture_size: (u32, u32), insets: Insets) -> Self {
+        let uv = texture::UvRect::from_pixels(x, y, width, height, texture_size);
+        Self::new(uv, [width as f32, height as f32], insets)
+    }
+
+    pub fn edges(mut self, mode: Mode) -> Self {
+        self.edges = mode;
+        self
+    }
+
+    pub fn centre(mut self, mode: Mode) -> Self {
+        self.centre = mode;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The quads of a panel with its top left corner at position. Borders wider than
+    /// the panel itself are shrunk to fit, the edges and centre then have no room.
+    pub fn pieces(&self, position: [f32; 3], size: [f32; 2]) -> Vec<Piece> {
+        let Insets { left, right, top, bottom } = self.insets;
+        let columns = sections(size[0], left * self.scale, right * self.scale);
+        let rows = sections(size[1], top * self.scale, bottom * self.scale);
+
+        // where the insets fall in the region, v pointing down like the rows
+        let u = [0.0, left / self.texels[0], 1.0 - right / self.texels[0], 1.0];
+        let v = [0.0, top / self.texels[1], 1.0 - bottom / self.texels[1], 1.0];
+        let widths = [left, self.texels[0] - left - right, right];
+        let heights = [top, self.texels[1] - top - bottom, bottom];
+
+        let mut pieces = Vec::new();
+        for row in 0..3 {
+            for column in 0..3 {
+                let mode = match (column, row) {
+                    (1, 1) => self.centre,
+                    (1, _) | (_, 1) => self.edges,
+                    _ => Mode::Stretch,
+                };
+                let uv = self.uv.sub([u[column], v[row]], [u[column + 1], v[row + 1]]);
+                let tile = [widths[column] * self.scale, heights[row] * self.scale];
+
+                let (x, width) = columns[column];
+                let (y, height) = rows[row];
+                for (x, width, fraction_x) in spans(x, width, tile[0], mode) {
+                    for (y, height, fraction_y) in spans(y, height, tile[1], mode) {
+                        pieces.push(Piece {
+                            position: [position[0] + x, position[1] - y, position[2]],
+                            size: [width, height],
+                            uv: uv.sub([0.0, 0.0], [fraction_x, fraction_y]),
+                        });
+                    }
+                }
+            }
+        }
+
+        pieces
+    }
+
+    /// The pieces as quads sampling whatever texture the region is in.
+    pub fn vecs(&self, position: [f32; 3], size: [f32; 2]) -> text::TextVecs {
+        let mut vecs = text::TextVecs::default();
+        for piece in self.pieces(position, size) {
+            vecs.extend(text::TextVecs::from_rect(piece.position, piece.size, piece.uv, None));
+        }
+
+        vecs
+    }
+}
+
+/// Start and length of the three sections across a panel, the borders shrunk to fit.
+fn sections(size: f32, start: f32, end: f32) -> [(f32, f32); 3] {
+    let shrink = if start + end > size && start + end > 0.0 { size / (start + end) } else { 1.0 };
+    let (start, end) = (start * shrink, end * shrink);
+
+    [(0.0, start), (start, size - start - end), (size - end, end)]
+}
+
+/// Start, length and the fraction of the tile used for each quad covering length from start.
+fn spans(start: f32, length: f32, tile: f32, mode: Mode) -> Vec<(f32, f32, f32)> {
+    if length <= 0.0 {
+        return Vec::new();
+    }
+    if mode == Mode::Stretch || tile <= 0.0 {
+        return vec![(start, length, 1.0)];
+    }
+
+    // leaves out slivers from rounding when length is a whole number of tiles
+    let count = (length / tile - 1e-3).ceil().max(1.0) as usize;
+    (0..count)
+        .map(|i| {
+            let offset = i as f32 * tile;
+            let used = tile.min(length - offset);
+            (start + offset, used, used / tile)
+        })
+        .collect()
+}
+
+/// A panel component drawn with the sprites, from a region of one layer of the sprite array.
+#[derive(Copy, Clone, Debug)]
+pub struct Panel {
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    pub slice: NineSlice,
+    pub layer: u32,
+}
+
+impl Panel {
+    pub fn sprite_quads(&self) -> impl Iterator<Item = sprite::SpriteQuad> + '_ {
+        self.slice.pieces(self.position, self.size)
+            .into_iter()
+            .map(|piece| sprite::SpriteQuad { position: piece.position, size: piece.size, uv: piece.uv, layer: self.layer })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_fit_borders() {
+        assert_eq!(sections(10.0, 2.0, 3.0), [(0.0, 2.0), (2.0, 5.0), (7.0, 3.0)]);
+        // too wide, shrunk in proportion and nothing left in the middle
+        assert_eq!(sections(4.0, 2.0, 6.0), [(0.0, 1.0), (1.0, 0.0), (1.0, 3.0)]);
+        assert_eq!(sections(5.0, 0.0, 0.0), [(0.0, 0.0), (0.0, 5.0), (5.0, 0.0)]);
+    }
+
+    #[test]
+    fn spans_tile_and_cut_the_last_short() {
+        assert_eq!(spans(1.0, 10.0, 4.0, Mode::Stretch), [(1.0, 10.0, 1.0)]);
+        assert_eq!(spans(1.0, 10.0, 4.0, Mode::Tile), [(1.0, 4.0, 1.0), (5.0, 4.0, 1.0), (9.0, 2.0, 0.5)]);
+        // a whole number of tiles leaves no sliver
+        assert_eq!(spans(0.0, 8.0, 4.0, Mode::Tile), [(0.0, 4.0, 1.0), (4.0, 4.0, 1.0)]);
+        assert_eq!(spans(0.0, 3.0, 4.0, Mode::Tile), [(0.0, 3.0, 0.75)]);
+        assert!(spans(0.0, 0.0, 4.0, Mode::Tile).is_empty());
+        // a tile with no size can't repeat
+        assert_eq!(spans(0.0, 3.0, 0.0, Mode::Tile), [(0.0, 3.0, 1.0)]);
+    }
+
+    #[test]
+    fn pieces_stretched() {
+        let slice = NineSlice::new(texture::UvRect::FULL, [8.0, 8.0], Insets::uniform(2.0));
+        let pieces = slice.pieces([10.0, 20.0, 0.5], [20.0, 12.0]);
+
+        assert_eq!(pieces.len(), 9);
+        // rows go down from the top left corner
+        assert_eq!(pieces[0].position, [10.0, 20.0, 0.5]);
+        assert_eq!(pieces[4].position, [12.0, 18.0, 0.5]);
+        assert_eq!(pieces[4].size, [16.0, 8.0]);
+        assert_eq!(pieces[4].uv, texture::UvRect { min: [0.25, 0.25], max: [0.75, 0.75] });
+        assert_eq!(pieces[8].position, [28.0, 10.0, 0.5]);
+        assert_eq!(pieces[8].uv, texture::UvRect { min: [0.75, 0.75], max: [1.0, 1.0] });
+    }
+
+    #[test]
+    fn pieces_tiled() {
+        let slice = NineSlice::new(texture::UvRect::FULL, [8.0, 8.0], Insets::uniform(2.0))
+            .edges(Mode::Tile)
+            .centre(Mode::Tile);
+        // a middle of 4 tiles across, the last a quarter used, and 1 down
+        let pieces = slice.pieces([0.0, 0.0, 0.0], [17.0, 8.0]);
+
+        let centre = pieces.iter()
+            .filter(|piece| piece.position[1] == -2.0 && piece.position[0] >= 2.0 && piece.position[0] < 15.0)
+            .collect::<Vec<_>>();
+        assert_eq!(centre.len(), 4);
+        assert_eq!(centre[3].position, [14.0, -2.0, 0.0]);
+        assert_eq!(centre[3].size, [1.0, 4.0]);
+        assert_eq!(centre[3].uv, texture::UvRect { min: [0.25, 0.25], max: [0.375, 0.75] });
+        // 4 corners, 4 + 4 across the top and bottom, 1 + 1 down the sides and the centre
+        assert_eq!(pieces.len(), 4 + 8 + 2 + 4);
+    }
+
+    #[test]
+    fn pieces_without_room_for_the_middle() {
+        let slice = NineSlice::new(texture::UvRect::FULL, [8.0, 8.0], Insets::uniform(2.0)).edges(Mode::Tile);
+        let pieces = slice.pieces([0.0, 0.0, 0.0], [2.0, 8.0]);
+
+        // the corners shrink to half, the top, bottom and centre have no width
+        assert_eq!(pieces.len(), 6);
+        assert!(pieces.iter().all(|piece| piece.size[0] == 1.0));
+    }
+}
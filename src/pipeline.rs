@@ -0,0 +1,307 @@
+/*
+Render pipeline creation.
+
+Shaders are checked with naga before wgpu sees them so mistakes are reported
+against the files they are in, and the pipeline itself is created inside a
+validation error scope, so a shader that doesn't match its layout or vertex
+buffers comes back as an error rather than a panic somewhere inside wgpu.
+
+PipelineCache keeps what it has built by everything that went into it (shader
+source, layout, vertex buffers, blend mode and target format), asking for the
+same pipeline again hands back the one already made.
+*/
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+
+use crate::blend;
+use crate::shader;
+
+/// What a pipeline draws into, it has to match the attachments of every pass it is used in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetFormat {
+    pub color: wgpu::TextureFormat,
+    pub depth: Option<wgpu::TextureFormat>,
+    pub sample_count: u32,
+}
+
+/// Runs create with wgpu's validation errors caught, label says what was being made.
+pub fn validated<T>(device: &wgpu::Device, label: &str, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+
+    match pollster::block_on(device.pop_error_scope()) {
+        None => Ok(value),
+        Some(wgpu::Error::Validation { description, .. }) => {
+            // what went wrong comes after a heading that is the same every time
+            let causes = description.split_once("Caused by:").map_or(description.as_str(), |(_, causes)| causes);
+            let causes = causes.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>();
+            Err(anyhow!("couldn't create {}:\n  {}", label, causes.join("\n  ")))
+        },
+        Some(e) => Err(anyhow!("couldn't create {}: {}", label, e)),
+    }
+}
+
+/// Only Opaque pipelines write depth, the others still test against what those wrote.
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    target_format: TargetFormat,
+    blend_mode: blend::BlendMode,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &shader::Shader,
+) -> Result<wgpu::RenderPipeline> {
+    shader.check()?;
+
+    validated(device, &format!("pipeline for {}", shader.name()), || {
+        let module = device.create_shader_module(shader.descriptor());
+
+        device.create_render_pipeline(
+            &wgpu::RenderPipelineDescriptor {
+                label: Some(shader.name()),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: vertex_layouts,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: target_format.color,
+                            blend: blend_mode.blend_state(),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })
+                    ],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: target_format.depth.map(|format| wgpu::DepthStencilState {
+                    format,
+                    depth_write_enabled: blend_mode.writes_depth(),
+                    // equal depth passes so later quads on the same z still draw over earlier ones
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: target_format.sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            }
+        )
+    })
+}
+
+// the layout is generic so keys can be made in tests, which have no device to make a layout with
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CacheKey<L = wgpu::Id<wgpu::PipelineLayout>> {
+    source: String,
+    layout: L,
+    // stride, step mode and attributes of each buffer
+    vertex_layouts: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    blend_mode: blend::BlendMode,
+    target_format: TargetFormat,
+}
+
+impl<L> CacheKey<L> {
+    fn new(
+        layout: L,
+        target_format: TargetFormat,
+        blend_mode: blend::BlendMode,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        shader: &shader::Shader,
+    ) -> Self {
+        Self {
+            source: shader.source.clone(),
+            layout,
+            vertex_layouts: vertex_layouts.iter()
+                .map(|buffer| (buffer.array_stride, buffer.step_mode, buffer.attributes.to_vec()))
+                .collect(),
+            blend_mode,
+            target_format,
+        }
+    }
+}
+
+/// Shared values by key, PipelineCache without the wgpu parts.
+struct Cache<K, V> {
+    entries: HashMap<K, Rc<V>>,
+}
+
+impl<K, V> Default for Cache<K, V> {
+    fn default() -> Self {
+        Self { entries: HashMap::new() }
+    }
+}
+
+impl<K: Eq + std::hash::Hash, V> Cache<K, V> {
+    /// The value made for key before, or the one create makes now. Errors aren't kept.
+    fn get_or_try_insert(&mut self, key: K, create: impl FnOnce() -> Result<V>) -> Result<Rc<V>> {
+        if let Some(value) = self.entries.get(&key) {
+            return Ok(value.clone());
+        }
+
+        let value = Rc::new(create()?);
+        self.entries.insert(key, value.clone());
+        Ok(value)
+    }
+
+    fn trim(&mut self) {
+        self.entries.retain(|_, value| Rc::strong_count(value) > 1);
+    }
+}
+
+/// Pipelines from create_render_pipeline, reused when asked for again.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: Cache<CacheKey, wgpu::RenderPipeline>,
+}
+
+impl PipelineCache {
+    /// create_render_pipeline, or the pipeline it made last time it was given the same things.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        target_format: TargetFormat,
+        blend_mode: blend::BlendMode,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        shader: &shader::Shader,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        let key = CacheKey::new(layout.global_id(), target_format, blend_mode, vertex_layouts, shader);
+
+        self.pipelines.get_or_try_insert(key, || {
+            create_render_pipeline(device, layout, target_format, blend_mode, vertex_layouts, shader)
+        })
+    }
+
+    /// A pipeline for every blend mode, drawing with the same shader.
+    pub fn blend_set(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        target_format: TargetFormat,
+        vertex_layout: wgpu::VertexBufferLayout,
+        shader: &shader::Shader,
+    ) -> Result<HashMap<blend::BlendMode, Rc<wgpu::RenderPipeline>>> {
+        blend::BlendMode::ALL.iter()
+            .map(|mode| {
+                let pipeline = self.get(device, layout, target_format, *mode, std::slice::from_ref(&vertex_layout), shader)?;
+                Ok((*mode, pipeline))
+            })
+            .collect()
+    }
+
+    /// Drops pipelines nothing outside the cache holds on to any more, like those of a shader since edited.
+    pub fn trim(&mut self) {
+        self.pipelines.trim();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: TargetFormat = TargetFormat {
+        color: wgpu::TextureFormat::Bgra8UnormSrgb,
+        depth: Some(wgpu::TextureFormat::Depth32Float),
+        sample_count: 1,
+    };
+
+    fn shader(source: &str) -> shader::Shader {
+        shader::Preprocessor::new().process("test.wgsl", source).unwrap()
+    }
+
+    fn vertex_layout(stride: wgpu::BufferAddress) -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: stride,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute { offset: 0, shader_location: 0, format: wgpu::VertexFormat::Float32x3 }],
+        }
+    }
+
+    #[test]
+    fn keys_differ_in_everything_that_goes_into_a_pipeline() {
+        let a = shader("fn a() {}");
+        let key = |layout: u32, format, mode, stride, shader: &shader::Shader| CacheKey::new(layout, format, mode, &[vertex_layout(stride)], shader);
+        let base = key(0, FORMAT, blend::BlendMode::Alpha, 12, &a);
+
+        // built again from the same things, including a shader processed twice
+        assert_eq!(base, key(0, FORMAT, blend::BlendMode::Alpha, 12, &shader("fn a() {}")));
+
+        assert_ne!(base, key(1, FORMAT, blend::BlendMode::Alpha, 12, &a));
+        assert_ne!(base, key(0, TargetFormat { sample_count: 4, ..FORMAT }, blend::BlendMode::Alpha, 12, &a));
+        assert_ne!(base, key(0, TargetFormat { depth: None, ..FORMAT }, blend::BlendMode::Alpha, 12, &a));
+        assert_ne!(base, key(0, FORMAT, blend::BlendMode::Opaque, 12, &a));
+        assert_ne!(base, key(0, FORMAT, blend::BlendMode::Alpha, 16, &a));
+        assert_ne!(base, key(0, FORMAT, blend::BlendMode::Alpha, 12, &shader("fn b() {}")));
+        assert_ne!(base, CacheKey::new(0, FORMAT, blend::BlendMode::Alpha, &[], &a));
+    }
+
+    #[test]
+    fn blend_set_modes_each_get_a_key() {
+        // blend_set asks for one pipeline per mode, they mustn't collapse into one entry
+        let a = shader("fn a() {}");
+        let mut cache = Cache::default();
+        for mode in blend::BlendMode::ALL {
+            cache.get_or_try_insert(CacheKey::new(0, FORMAT, mode, &[vertex_layout(12)], &a), || Ok(mode)).unwrap();
+        }
+
+        assert_eq!(cache.entries.len(), blend::BlendMode::ALL.len());
+    }
+
+    #[test]
+    fn hands_back_what_it_made() {
+        let mut cache = Cache::default();
+        let mut made = 0;
+        let mut get = |cache: &mut Cache<&str, u32>, key| cache.get_or_try_insert(key, || {
+            made += 1;
+            Ok(made)
+        });
+
+        let first = get(&mut cache, "a").unwrap();
+        let again = get(&mut cache, "a").unwrap();
+        assert!(Rc::ptr_eq(&first, &again));
+        assert_eq!(*get(&mut cache, "b").unwrap(), 2);
+
+        // a failed create is tried again next time
+        assert!(cache.get_or_try_insert("c", || Err(anyhow!("broken"))).is_err());
+        assert_eq!(*cache.get_or_try_insert("c", || Ok(3)).unwrap(), 3);
+    }
+
+    #[test]
+    fn trim_drops_only_what_nothing_else_holds() {
+        let mut cache = Cache::default();
+        let held = cache.get_or_try_insert("held", || Ok(1)).unwrap();
+        cache.get_or_try_insert("dropped", || Ok(2)).unwrap();
+
+        cache.trim();
+        assert_eq!(cache.entries.keys().copied().collect::<Vec<_>>(), ["held"]);
+
+        drop(held);
+        cache.trim();
+        assert!(cache.entries.is_empty());
+    }
+}
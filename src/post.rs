@@ -0,0 +1,461 @@
+/*
+Post processing.
+
+With any effect enabled the scene is drawn into a transient target instead of
+the swapchain, then add_passes puts each enabled effect into the render graph
+in order as a fullscreen pass, each into its own transient target (which the
+graph aliases down to two), with the last one writing to the output.
+
+Effects are src/post/<name>.wgsl, each including src/post/fullscreen.wgsl, and all
+share one bind group layout: the source image at 0/1, the effect's params at 2
+and the colour grading table at 3/4 (ignored by the others).
+*/
+
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::blend;
+use crate::graph;
+use crate::pipeline;
+use crate::shader;
+use crate::stats;
+use crate::texture;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub intensity: f32,
+    /// Spacing of the blur taps in pixels
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self { threshold: 0.8, intensity: 0.6, radius: 3.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Vignette {
+    pub intensity: f32,
+    /// Distance from the centre where darkening starts, 0.5 is the middle of an edge
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self { intensity: 0.5, radius: 0.5, softness: 0.3 }
+    }
+}
+
+/// Grades through the chain's lookup table, see PostChain::set_lut.
+#[derive(Copy, Clone, Debug)]
+pub struct ColorGrade {
+    pub strength: f32,
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        Self { strength: 1.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Crt {
+    pub curvature: f32,
+    pub scanline_intensity: f32,
+    pub scanline_count: f32,
+    pub mask_intensity: f32,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Self { curvature: 0.15, scanline_intensity: 0.3, scanline_count: 240.0, mask_intensity: 0.2 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ChromaticAberration {
+    /// Offset in pixels at the corners
+    pub strength: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { strength: 3.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Fxaa {
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self { span_max: 8.0, reduce_mul: 1.0 / 8.0, reduce_min: 1.0 / 128.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Effect {
+    Bloom(Bloom),
+    Vignette(Vignette),
+    ColorGrade(ColorGrade),
+    Crt(Crt),
+    ChromaticAberration(ChromaticAberration),
+    Fxaa(Fxaa),
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Bloom(_) => "bloom",
+            Effect::Vignette(_) => "vignette",
+            Effect::ColorGrade(_) => "color_grade",
+            Effect::Crt(_) => "crt",
+            Effect::ChromaticAberration(_) => "chromatic_aberration",
+            Effect::Fxaa(_) => "fxaa",
+        }
+    }
+
+    /// Packed the way the effect's Params struct expects, every one fits in a vec4.
+    fn params(&self, lut_size: u32) -> [f32; 4] {
+        match *self {
+            Effect::Bloom(p) => [p.threshold, p.intensity, p.radius, 0.0],
+            Effect::Vignette(p) => [p.intensity, p.radius, p.softness, 0.0],
+            Effect::ColorGrade(p) => [p.strength, lut_size as f32, 0.0, 0.0],
+            Effect::Crt(p) => [p.curvature, p.scanline_intensity, p.scanline_count, p.mask_intensity],
+            Effect::ChromaticAberration(p) => [p.strength, 0.0, 0.0, 0.0],
+            Effect::Fxaa(p) => [p.span_max, p.reduce_mul, p.reduce_min, 0.0],
+        }
+    }
+}
+
+/// One effect in the chain with its own pipeline and params buffer.
+pub struct PostEffect {
+    pub effect: Effect,
+    pub enabled: bool,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    params: wgpu::Buffer,
+}
+
+pub struct PostChain {
+    effects: Vec<PostEffect>,
+    format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    lut: texture::Texture,
+    lut_size: u32,
+}
+
+impl PostChain {
+    /// format is what the effects read and write, including the final output.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0),
+                    sampler_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    texture_entry(3),
+                    sampler_entry(4),
+                ],
+                label: Some("post_bind_group_layout"),
+            }
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Post Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            }
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let lut_size = 16;
+        let lut = identity_lut(device, queue, lut_size);
+
+        Self {
+            effects: Vec::new(),
+            format,
+            bind_group_layout,
+            pipeline_layout,
+            sampler,
+            lut,
+            lut_size,
+        }
+    }
+
+    /// Adds an effect to the end of the chain, returns its index.
+    pub fn push(&mut self, device: &wgpu::Device, cache: &mut pipeline::PipelineCache, effect: Effect) -> anyhow::Result<usize> {
+        let index = self.effects.len();
+        self.insert(device, cache, index, effect)?;
+        Ok(index)
+    }
+
+    pub fn insert(&mut self, device: &wgpu::Device, cache: &mut pipeline::PipelineCache, index: usize, effect: Effect) -> anyhow::Result<()> {
+        let pipeline = self.create_pipeline(device, cache, &effect)?;
+
+        let params = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Post Params Buffer"),
+                contents: bytemuck::cast_slice(&effect.params(self.lut_size)),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        self.effects.insert(index, PostEffect { effect, enabled: true, pipeline, params });
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        cache: &mut pipeline::PipelineCache,
+        effect: &Effect,
+    ) -> anyhow::Result<Rc<wgpu::RenderPipeline>> {
+        let shader = shader::Preprocessor::new().process_file(&format!("post/{}.wgsl", effect.name()))?;
+        let target_format = pipeline::TargetFormat { color: self.format, depth: None, sample_count: 1 };
+
+        cache.get(device, &self.pipeline_layout, target_format, blend::BlendMode::Opaque, &[], &shader)
+    }
+
+    /// Rebuilds every effect's pipeline from its shader, keeping the old one for any that fail.
+    pub fn reload(&mut self, device: &wgpu::Device, cache: &mut pipeline::PipelineCache) -> Vec<anyhow::Error> {
+        let mut errors = Vec::new();
+
+        for i in 0..self.effects.len() {
+            match self.create_pipeline(device, cache, &self.effects[i].effect) {
+                Ok(pipeline) => self.effects[i].pipeline = pipeline,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        errors
+    }
+
+    pub fn remove(&mut self, index: usize) -> Effect {
+        self.effects.remove(index).effect
+    }
+
+    /// Changes an effect's params, it has to stay the same kind of effect.
+    pub fn set(&mut self, queue: &wgpu::Queue, index: usize, effect: Effect) {
+        let slot = &mut self.effects[index];
+        assert_eq!(slot.effect.name(), effect.name(), "post effect {} can't become a different effect", index);
+
+        let params = effect.params(self.lut_size);
+        queue.write_buffer(&slot.params, 0, bytemuck::cast_slice(&params));
+        stats::count_upload(std::mem::size_of_val(&params) as u64);
+        slot.effect = effect;
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.effects[index].enabled = enabled;
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    /// Replaces the colour grading table, a (size * size) x size strip of slices. It is looked
+    /// up with sRGB values and holds sRGB values, like tables exported from image editors.
+    pub fn set_lut(&mut self, queue: &wgpu::Queue, lut: texture::Texture) -> anyhow::Result<()> {
+        let size = lut.texture.height();
+        anyhow::ensure!(
+            lut.texture.width() == size * size,
+            "a {}x{} lookup table should be {}x{}", lut.texture.width(), size, size * size, size
+        );
+
+        self.lut = lut;
+        self.lut_size = size;
+        for slot in &self.effects {
+            let params = slot.effect.params(size);
+            queue.write_buffer(&slot.params, 0, bytemuck::cast_slice(&params));
+            stats::count_upload(std::mem::size_of_val(&params) as u64);
+        }
+
+        Ok(())
+    }
+
+    /// Memory taken by the grading table, the textures effects draw into belong to the render graph.
+    pub fn memory(&self) -> u64 {
+        stats::texture_bytes(&self.lut.texture)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|slot| slot.enabled)
+    }
+
+    /// Adds a pass per enabled effect, reading source and finishing in output.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut graph::RenderGraph<'a>,
+        source: graph::ResourceId,
+        output: graph::ResourceId,
+        width: u32,
+        height: u32,
+    ) {
+        let enabled = self.effects.iter().filter(|slot| slot.enabled).collect::<Vec<_>>();
+        let count = enabled.len();
+
+        let mut source = source;
+        for (i, slot) in enabled.into_iter().enumerate() {
+            let target = if i + 1 == count {
+                output
+            } else {
+                graph.create_texture(slot.effect.name(), graph::TextureDesc::color(width, height, self.format))
+            };
+
+            graph.add_pass(slot.effect.name(), &[source], &[target], move |ctx, encoder| {
+                self.record(ctx.device, encoder, slot, ctx.view(source), ctx.view(target));
+            });
+
+            source = target;
+        }
+    }
+
+    fn record(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        slot: &PostEffect,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        // cheap enough to remake each frame, and never stale after a resize
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("post_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: slot.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.lut.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(slot.effect.name()),
+            color_attachments: &[Some(texture::color_attachment(target, None, wgpu::Color::BLACK))],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&slot.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        stats::count_draw(3);
+    }
+}
+
+/// A table that maps every colour to itself.
+fn identity_lut(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> texture::Texture {
+    let max = (size - 1) as f32;
+    let img = image::RgbaImage::from_fn(size * size, size, |x, y| {
+        let channel = |v: u32| (v as f32 / max * 255.0).round() as u8;
+        image::Rgba([channel(x % size), channel(y), channel(x / size), 255])
+    });
+
+    let options = texture::TextureOptions::new()
+        .linear()
+        .filter(wgpu::FilterMode::Linear);
+
+    texture::Texture::from_image_with_options(device, queue, &image::DynamicImage::ImageRgba8(img), Some("Identity LUT"), &options)
+        .expect("identity lookup table")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_effect() -> [Effect; 6] {
+        [
+            Effect::Bloom(Bloom::default()),
+            Effect::Vignette(Vignette::default()),
+            Effect::ColorGrade(ColorGrade::default()),
+            Effect::Crt(Crt::default()),
+            Effect::ChromaticAberration(ChromaticAberration::default()),
+            Effect::Fxaa(Fxaa::default()),
+        ]
+    }
+
+    #[test]
+    fn every_effect_shader_compiles() {
+        // what push builds its pipeline from
+        for effect in every_effect() {
+            let file = format!("post/{}.wgsl", effect.name());
+            if let Err(e) = shader::Preprocessor::new().compile_file(&file) {
+                panic!("{}: {:?}", file, e);
+            }
+        }
+    }
+
+    #[test]
+    fn params_in_field_order() {
+        let vignette = Effect::Vignette(Vignette { intensity: 0.1, radius: 0.2, softness: 0.3 });
+        assert_eq!(vignette.params(16), [0.1, 0.2, 0.3, 0.0]);
+
+        let crt = Effect::Crt(Crt { curvature: 1.0, scanline_intensity: 2.0, scanline_count: 3.0, mask_intensity: 4.0 });
+        assert_eq!(crt.params(16), [1.0, 2.0, 3.0, 4.0]);
+
+        // only the grade needs the table's size
+        let grade = Effect::ColorGrade(ColorGrade { strength: 0.5 });
+        assert_eq!(grade.params(32), [0.5, 32.0, 0.0, 0.0]);
+        for effect in every_effect().into_iter().filter(|effect| effect.name() != "color_grade") {
+            assert_eq!(effect.params(16), effect.params(32), "{}", effect.name());
+        }
+    }
+}
@@ -0,0 +1,38 @@
+// Single pass bloom: a gaussian weighted 5x5 gather of everything brighter than the threshold
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    threshold: f32,
+    intensity: f32,
+    // spacing of the taps in pixels
+    radius: f32,
+    _padding: f32,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+
+fn bright(color: vec3<f32>) -> vec3<f32> {
+    let l = luma(color);
+    return color * max(l - params.threshold, 0.0) / max(l, 0.0001);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base = source(in.tex_coords);
+    let step = texel_size() * params.radius;
+
+    var sum = vec3(0.0);
+    var total = 0.0;
+    for (var x = -2; x <= 2; x++) {
+        for (var y = -2; y <= 2; y++) {
+            let offset = vec2(f32(x), f32(y));
+            let weight = exp(-dot(offset, offset) / 4.5);
+            sum += bright(source(in.tex_coords + offset * step).rgb) * weight;
+            total += weight;
+        }
+    }
+
+    return vec4(base.rgb + sum / total * params.intensity, base.a);
+}
@@ -0,0 +1,26 @@
+// Splits red and blue apart towards the edges of the screen
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    // offset in pixels at the corners
+    strength: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = in.tex_coords - vec2(0.5);
+    let offset = direction * 2.0 * params.strength * texel_size();
+
+    let color = source(in.tex_coords);
+    let r = source(in.tex_coords + offset).r;
+    let b = source(in.tex_coords - offset).b;
+
+    return vec4(r, color.g, b, color.a);
+}
@@ -0,0 +1,40 @@
+// Colour grading through a 3D lookup table stored as a strip of slices,
+// (size * size) x size with blue picking the slice. Tables are authored
+// against sRGB values so the lookup happens in that space.
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    strength: f32,
+    lut_size: f32,
+    _padding: vec2<f32>,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+@group(0)@binding(3)
+var t_lut: texture_2d<f32>;
+@group(0)@binding(4)
+var s_lut: sampler;
+
+fn slice(rg: vec2<f32>, b: f32) -> vec3<f32> {
+    let size = params.lut_size;
+    let uv = vec2((b * size + rg.x + 0.5) / (size * size), (rg.y + 0.5) / size);
+    return textureSampleLevel(t_lut, s_lut, uv, 0.0).rgb;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = source(in.tex_coords);
+    let scaled = clamp(to_srgb(color.rgb), vec3(0.0), vec3(1.0)) * (params.lut_size - 1.0);
+
+    // blue is between two slices, filtering only handles red and green
+    let b = floor(scaled.b);
+    let graded = mix(
+        slice(scaled.rg, b),
+        slice(scaled.rg, min(b + 1.0, params.lut_size - 1.0)),
+        scaled.b - b,
+    );
+
+    return vec4(mix(color.rgb, to_linear(graded), params.strength), color.a);
+}
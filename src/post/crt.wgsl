@@ -0,0 +1,32 @@
+// Curved screen, scanlines and an aperture grille
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    curvature: f32,
+    scanline_intensity: f32,
+    scanline_count: f32,
+    mask_intensity: f32,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let centred = in.tex_coords * 2.0 - 1.0;
+    let bent = centred * (1.0 + params.curvature * dot(centred, centred) * 0.25);
+    let uv = bent * 0.5 + 0.5;
+
+    let color = source(uv);
+    let inside = all(uv >= vec2(0.0)) && all(uv <= vec2(1.0));
+
+    let scanline = 1.0 - params.scanline_intensity * (0.5 + 0.5 * cos(uv.y * params.scanline_count * 6.2831853));
+
+    // every third pixel column favours one of red, green and blue
+    let column = u32(in.clip_position.x) % 3u;
+    var mask = vec3(1.0 - params.mask_intensity);
+    mask[column] = 1.0;
+
+    return vec4(select(vec3(0.0), color.rgb * scanline * mask, inside), color.a);
+}
@@ -0,0 +1,18 @@
+// Shared by every post effect: a fullscreen triangle and the image being processed.
+// Effects add their params at binding 2 and anything else from binding 3 on.
+
+#include "shader/fullscreen.wgsl"
+#include "shader/color.wgsl"
+
+@group(0)@binding(0)
+var t_source: texture_2d<f32>;
+@group(0)@binding(1)
+var s_source: sampler;
+
+fn source(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0);
+}
+
+fn texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_source));
+}
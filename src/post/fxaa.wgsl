@@ -0,0 +1,51 @@
+// FXAA, the short version: blur along the edge direction found from the luma of the corners
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    span_max: f32,
+    reduce_mul: f32,
+    reduce_min: f32,
+    _padding: f32,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = texel_size();
+    let uv = in.tex_coords;
+
+    let luma_nw = luma(source(uv + vec2(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(source(uv + vec2(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(source(uv + vec2(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(source(uv + vec2(1.0, 1.0) * texel).rgb);
+    let centre = source(uv);
+    let luma_m = luma(centre.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.reduce_mul, params.reduce_min);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-params.span_max), vec2(params.span_max)) * texel;
+
+    let a = 0.5 * (
+        source(uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        source(uv + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let b = a * 0.5 + 0.25 * (
+        source(uv - direction * 0.5).rgb +
+        source(uv + direction * 0.5).rgb
+    );
+
+    let luma_b = luma(b);
+    let result = select(b, a, luma_b < luma_min || luma_b > luma_max);
+
+    return vec4(result, centre.a);
+}
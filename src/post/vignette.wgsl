@@ -0,0 +1,23 @@
+// Darkens towards the corners
+
+#include "post/fullscreen.wgsl"
+
+struct Params {
+    intensity: f32,
+    // distance from the centre (0.5 is the middle of an edge) where darkening starts
+    radius: f32,
+    softness: f32,
+    _padding: f32,
+}
+
+@group(0)@binding(2)
+var<uniform> params: Params;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = source(in.tex_coords);
+    let distance = length(in.tex_coords - vec2(0.5));
+    let shade = smoothstep(params.radius + params.softness, params.radius, distance);
+
+    return vec4(color.rgb * mix(1.0, shade, params.intensity), color.a);
+}
@@ -1,13 +1,54 @@
-use std::io::{BufReader, Cursor};
-
-use wgpu::util::DeviceExt;
+use std::path::Path;
+use std::sync::RwLock;
 
 use anyhow::Ok;
 
+use crate::archive;
+use crate::atlas;
+use crate::compressed;
+use crate::sprite_sheet;
 use crate::texture;
 
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+    pub fn get(file_name: &str) -> Option<&'static [u8]> {
+        EMBEDDED_ASSETS.iter()
+            .find(|(name, _)| *name == file_name)
+            .map(|(_, data)| *data)
+    }
+}
+
+// Mounted archives are searched newest first, before the loose files in res/
+static ARCHIVES: RwLock<Vec<archive::Archive>> = RwLock::new(Vec::new());
+
+pub fn mount_archive<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
+    let archive = archive::Archive::open(path)?;
+    ARCHIVES.write().unwrap().push(archive);
+
+    Ok(())
+}
 
+/// Looks in, in order: mounted archives, then with the embed-assets feature res/ next
+/// to the executable (overrides) and the embedded table, and finally the build's res/ copy.
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    for archive in ARCHIVES.read().unwrap().iter().rev() {
+        if archive.contains(file_name) {
+            return archive.read(file_name);
+        }
+    }
+
+    #[cfg(feature = "embed-assets")]
+    {
+        if let Some(path) = override_path(file_name) {
+            return Ok(std::fs::read(path)?);
+        }
+        if let Some(data) = embedded::get(file_name) {
+            return Ok(data.to_vec());
+        }
+    }
+
     let path = std::path::Path::new(env!("OUT_DIR"))
         .join("res")
         .join(file_name);
@@ -16,11 +57,123 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     Ok(data)
 }
 
+#[cfg(feature = "embed-assets")]
+fn override_path(file_name: &str) -> Option<std::path::PathBuf> {
+    let path = std::env::current_exe().ok()?
+        .parent()?
+        .join("res")
+        .join(file_name);
+
+    path.is_file().then_some(path)
+}
+
+/// Reads `<file_name>.meta` if there is one, otherwise the default options.
+pub async fn load_texture_options(file_name: &str) -> anyhow::Result<texture::TextureOptions> {
+    match load_binary(&format!("{}.meta", file_name)).await {
+        Result::Ok(data) => texture::TextureOptions::from_sidecar(std::str::from_utf8(&data)?)
+            .map_err(|e| e.context(format!("in {}.meta", file_name))),
+        Err(e) if is_not_found(&e) => Ok(texture::TextureOptions::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
-}
\ No newline at end of file
+    let options = load_texture_options(file_name).await?;
+
+    if compressed::CompressedImage::is_container(&data) {
+        let img = compressed::CompressedImage::parse(&data)?;
+        return texture::Texture::from_compressed(device, queue, &img, Some(file_name), &options);
+    }
+
+    let img = image::load_from_memory(&data)?;
+    texture::Texture::from_image_with_options(device, queue, &img, Some(file_name), &options)
+}
+
+/// Packs a set of loose images into atlas pages, each region is named by its file name.
+pub async fn load_atlas(
+    file_names: &[&str],
+    page_size: u32,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<atlas::Atlas> {
+    let mut builder = atlas::AtlasBuilder::new(page_size);
+    for file_name in file_names {
+        let data = load_binary(file_name).await?;
+        builder.add(file_name, image::load_from_memory(&data)?.to_rgba8());
+    }
+
+    builder.build()?.upload(device, queue, "atlas", &texture::TextureOptions::default())
+}
+
+/// Loads a TexturePacker or Aseprite json sheet and the image it names, relative to the json.
+pub async fn load_sprite_sheet(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<sprite_sheet::SpriteSheet> {
+    let json = load_binary(file_name).await?;
+    let description = sprite_sheet::SheetDescription::parse(&json, None)?;
+
+    let image_name = match file_name.rsplit_once('/') {
+        Some((dir, _)) => format!("{}/{}", dir, description.image),
+        None => description.image.clone(),
+    };
+    let texture = load_texture(&image_name, device, queue).await?;
+
+    Ok(description.with_texture(texture))
+}
+
+/// Loads same sized images into the layers of one texture array, in the order given.
+pub async fn load_texture_array(
+    file_names: &[&str],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::TextureArray> {
+    let (images, options) = load_array_images(file_names).await?;
+    texture::TextureArray::from_images(device, queue, &images, Some("texture array"), &options)
+}
+
+/// The decoded layers of a texture array and the options to upload them with.
+pub async fn load_array_images(file_names: &[&str]) -> anyhow::Result<(Vec<image::RgbaImage>, texture::TextureOptions)> {
+    let mut images = Vec::with_capacity(file_names.len());
+    for file_name in file_names {
+        let data = load_binary(file_name).await?;
+        images.push(image::load_from_memory(&data)?.to_rgba8());
+    }
+
+    // the first texture's sidecar decides filtering for the whole array
+    let options = load_texture_options(file_names.first().copied().unwrap_or_default()).await?;
+    Ok((images, options))
+}
+
+/// Loads a colour grading table for post::PostChain::set_lut. The values are
+/// data rather than colours, so they are kept as stored and filtered linearly.
+pub async fn load_lut(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let data = load_binary(file_name).await?;
+    let img = image::load_from_memory(&data)?;
+    let options = texture::TextureOptions::new()
+        .linear()
+        .filter(wgpu::FilterMode::Linear);
+
+    texture::Texture::from_image_with_options(device, queue, &img, Some(file_name), &options)
+}
+
+/// Reads a shader, for material::Material::new.
+pub async fn load_shader(file_name: &str) -> anyhow::Result<String> {
+    let data = load_binary(file_name).await?;
+    Ok(String::from_utf8(data)?)
+}
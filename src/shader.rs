@@ -0,0 +1,464 @@
+/*
+WGSL preprocessing.
+
+Every shader goes through a Preprocessor before wgpu sees it, which handles
+lines starting with:
+ #include "shader/camera.wgsl"   pastes in another file, once per shader however often it is included
+ #define NAME value              replaces NAME with value in the lines after it, value can be left out
+ #undef NAME
+ #ifdef NAME / #ifndef NAME / #else / #endif
+
+Files are named by their path under src/ and all of them are built in. Shared
+modules live in src/shader/: camera.wgsl, color.wgsl, fullscreen.wgsl and
+sprite.wgsl. Defines carry across includes like in C, so a define made before
+an include is seen inside it.
+
+The processed source remembers which file and line each of its lines came
+from, and Shader::check reports naga's errors against those rather than the
+pasted together result.
+
+Development builds read the files from src/ on disk rather than the copies
+built in, and Watcher notices when one is saved so the pipelines using it can
+be rebuilt while running.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time;
+
+use anyhow::{anyhow, bail, ensure, Context, Error, Result};
+
+/// Every shader file under src/ by the name it is included with.
+const FILES: &[(&str, &str)] = &[
+    ("shader/camera.wgsl", include_str!("shader/camera.wgsl")),
+    ("shader/color.wgsl", include_str!("shader/color.wgsl")),
+    ("shader/fullscreen.wgsl", include_str!("shader/fullscreen.wgsl")),
+    ("shader/sprite.wgsl", include_str!("shader/sprite.wgsl")),
+    ("text.wgsl", include_str!("text.wgsl")),
+    ("sprite_array.wgsl", include_str!("sprite_array.wgsl")),
+    ("blit.wgsl", include_str!("blit.wgsl")),
+    ("debug.wgsl", include_str!("debug.wgsl")),
+    ("shape.wgsl", include_str!("shape.wgsl")),
+    ("post/fullscreen.wgsl", include_str!("post/fullscreen.wgsl")),
+    ("post/bloom.wgsl", include_str!("post/bloom.wgsl")),
+    ("post/vignette.wgsl", include_str!("post/vignette.wgsl")),
+    ("post/color_grade.wgsl", include_str!("post/color_grade.wgsl")),
+    ("post/crt.wgsl", include_str!("post/crt.wgsl")),
+    ("post/chromatic_aberration.wgsl", include_str!("post/chromatic_aberration.wgsl")),
+    ("post/fxaa.wgsl", include_str!("post/fxaa.wgsl")),
+    ("material/flash.wgsl", include_str!("material/flash.wgsl")),
+    ("material/dissolve.wgsl", include_str!("material/dissolve.wgsl")),
+    ("material/outline.wgsl", include_str!("material/outline.wgsl")),
+];
+
+/// Whether shaders come from src/ on disk and are watched for changes.
+pub const HOT_RELOAD: bool = cfg!(debug_assertions);
+
+fn disk_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src").join(name)
+}
+
+/// A shader file by name, from disk when hot reloading.
+pub fn file(name: &str) -> Result<String> {
+    if HOT_RELOAD {
+        if let Ok(source) = std::fs::read_to_string(disk_path(name)) {
+            return Ok(source);
+        }
+    }
+
+    FILES.iter()
+        .find(|(file_name, _)| *file_name == name)
+        .map(|(_, source)| source.to_string())
+        .with_context(|| format!("no shader file {}", name))
+}
+
+/// Notices shader files being saved by polling their modification times.
+pub struct Watcher {
+    modified: HashMap<&'static str, time::SystemTime>,
+    last_poll: time::Instant,
+}
+
+impl Watcher {
+    const POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+    pub fn new() -> Self {
+        Self {
+            modified: Self::modified_times(),
+            last_poll: time::Instant::now(),
+        }
+    }
+
+    fn modified_times() -> HashMap<&'static str, time::SystemTime> {
+        FILES.iter()
+            .filter_map(|(name, _)| {
+                let modified = std::fs::metadata(disk_path(name)).and_then(|m| m.modified()).ok()?;
+                Some((*name, modified))
+            })
+            .collect()
+    }
+
+    /// Files saved since the last call, only looks at the disk every POLL_INTERVAL.
+    pub fn changed(&mut self) -> Vec<&'static str> {
+        if self.last_poll.elapsed() < Self::POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = time::Instant::now();
+
+        let modified = Self::modified_times();
+        let changed = modified.iter()
+            .filter(|(name, time)| self.modified.get(*name) != Some(time))
+            .map(|(name, _)| *name)
+            .collect();
+
+        self.modified = modified;
+        changed
+    }
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Preprocessor {
+    defines: HashMap<String, String>,
+    modules: HashMap<String, String>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// As if the shader started with `#define name value`.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Defines SCREEN_SPACE if set, for shaders that can also draw without the camera.
+    pub fn screen_space(self, screen_space: bool) -> Self {
+        if screen_space { self.define("SCREEN_SPACE", "") } else { self }
+    }
+
+    /// Makes source includable as name, taking priority over a built in file of the same name.
+    pub fn module(mut self, name: &str, source: &str) -> Self {
+        self.modules.insert(name.to_string(), source.to_string());
+        self
+    }
+
+    /// Processes a built in file (or module) by name.
+    pub fn process_file(&self, name: &str) -> Result<Shader> {
+        let source = self.lookup(name)?;
+        self.process(name, &source)
+    }
+
+    /// name is what errors in source get reported against.
+    pub fn process(&self, name: &str, source: &str) -> Result<Shader> {
+        let mut shader = Shader {
+            name: name.to_string(),
+            source: String::new(),
+            files: Vec::new(),
+            lines: Vec::new(),
+        };
+        let mut state = State {
+            defines: self.defines.clone(),
+            included: HashSet::from([name.to_string()]),
+        };
+
+        self.process_into(name, source, &mut shader, &mut state)?;
+        Ok(shader)
+    }
+
+    /// process_file followed by Shader::check.
+    pub fn compile_file(&self, name: &str) -> Result<Shader> {
+        let shader = self.process_file(name)?;
+        shader.check()?;
+        Ok(shader)
+    }
+
+    fn lookup(&self, name: &str) -> Result<String> {
+        match self.modules.get(name) {
+            Some(source) => Ok(source.clone()),
+            None => file(name),
+        }
+    }
+
+    fn process_into(&self, name: &str, source: &str, shader: &mut Shader, state: &mut State) -> Result<()> {
+        let file_index = shader.files.len();
+        shader.files.push(name.to_string());
+
+        // one entry per #ifdef we are inside of: whether its lines are kept, whether #else was seen
+        // and the line it started on, to point at when it is never closed
+        let mut conditions: Vec<(bool, bool, usize)> = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let at = || format!("{}:{}", name, line_number);
+            let active = conditions.iter().all(|(keep, _, _)| *keep);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    shader.source += &substitute(line, &state.defines);
+                    shader.source.push('\n');
+                    shader.lines.push((file_index, line_number));
+                }
+                continue;
+            };
+
+            // comments after a directive aren't part of it
+            let directive = directive.split("//").next().unwrap_or_default();
+            let mut words = directive.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let argument = words.next();
+
+            match (keyword, argument) {
+                ("ifdef", Some(define)) => conditions.push((state.defines.contains_key(define), false, line_number)),
+                ("ifndef", Some(define)) => conditions.push((!state.defines.contains_key(define), false, line_number)),
+                ("else", None) => match conditions.last_mut() {
+                    Some((keep, seen_else, _)) if !*seen_else => {
+                        *keep = !*keep;
+                        *seen_else = true;
+                    },
+                    Some(_) => bail!("{}: second #else for the same #ifdef", at()),
+                    None => bail!("{}: #else without #ifdef", at()),
+                },
+                ("endif", None) => {
+                    ensure!(conditions.pop().is_some(), "{}: #endif without #ifdef", at());
+                },
+                // the rest only count in lines that are kept
+                _ if !active => {},
+                ("define", Some(define)) => {
+                    // the value is everything after the name, spaces included
+                    let value = directive.trim_start()["define".len()..].trim_start()[define.len()..].trim();
+                    state.defines.insert(define.to_string(), value.to_string());
+                },
+                ("undef", Some(define)) => {
+                    state.defines.remove(define);
+                },
+                ("include", Some(_)) => {
+                    let include = directive.trim_start()["include".len()..].trim();
+                    let include = include.strip_prefix('"')
+                        .and_then(|include| include.strip_suffix('"'))
+                        .with_context(|| format!("{}: #include needs a quoted file name", at()))?;
+
+                    if state.included.insert(include.to_string()) {
+                        let source = self.lookup(include).map_err(|e| anyhow!("{}: {}", at(), e))?;
+                        self.process_into(include, &source, shader, state)?;
+                    }
+                },
+                _ => bail!("{}: can't make sense of #{}", at(), directive.trim()),
+            }
+        }
+
+        if let Some((_, _, line_number)) = conditions.last() {
+            bail!("{}:{}: #ifdef without #endif", name, line_number);
+        }
+
+        Ok(())
+    }
+}
+
+struct State {
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+}
+
+/// Replaces whole identifiers that have a value, leaving comments alone.
+fn substitute(line: &str, defines: &HashMap<String, String>) -> String {
+    if defines.values().all(|value| value.is_empty()) {
+        return line.to_string();
+    }
+
+    let (code, comment) = match line.find("//") {
+        Some(index) => line.split_at(index),
+        None => (line, ""),
+    };
+
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(line.len());
+    let mut rest = code;
+
+    while let Some(start) = rest.find(is_word) {
+        out += &rest[..start];
+        let length = rest[start..].find(|c| !is_word(c)).unwrap_or(rest.len() - start);
+        let word = &rest[start..start + length];
+
+        // numbers like 1e5 are words too but never names
+        match defines.get(word) {
+            Some(value) if !value.is_empty() && !word.starts_with(|c: char| c.is_ascii_digit()) => out += value,
+            _ => out += word,
+        }
+        rest = &rest[start + length..];
+    }
+    out += rest;
+
+    out + comment
+}
+
+/// WGSL ready for wgpu, with where each of its lines came from.
+#[derive(Debug)]
+pub struct Shader {
+    name: String,
+    pub source: String,
+    files: Vec<String>,
+    // (index into files, 1 based line) for each line of source
+    lines: Vec<(usize, usize)>,
+}
+
+impl Shader {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The file and line a 1 based line of the processed source came from.
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let (file, line) = *self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[file], line))
+    }
+
+    pub fn descriptor(&self) -> wgpu::ShaderModuleDescriptor<'_> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some(&self.name),
+            source: wgpu::ShaderSource::Wgsl(self.source.as_str().into()),
+        }
+    }
+
+    /// Parses and validates the shader with naga, the same way wgpu would, but with
+    /// errors pointing into the files the shader was put together from.
+    pub fn check(&self) -> Result<()> {
+        let module = naga::front::wgsl::parse_str(&self.source)
+            .map_err(|e| self.diagnostic(e.message(), e.labels().map(|(span, label)| (span, label.to_string()))))?;
+
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| {
+                // the top level message is vague, the causes say what is actually wrong
+                let mut message = e.to_string();
+                let mut source = std::error::Error::source(e.as_inner());
+                while let Some(cause) = source {
+                    message += &format!(": {}", cause);
+                    source = cause.source();
+                }
+                self.diagnostic(&message, e.spans().cloned())
+            })?;
+
+        Ok(())
+    }
+
+    fn diagnostic(&self, message: &str, spans: impl Iterator<Item = (naga::Span, String)>) -> Error {
+        let mut text = format!("error in shader {}: {}", self.name, message);
+
+        for (span, label) in spans {
+            if !span.is_defined() {
+                continue;
+            }
+
+            let location = span.location(&self.source);
+            let line = location.line_number as usize;
+            let code = self.source.lines().nth(line - 1).unwrap_or_default();
+
+            match self.origin(line) {
+                Some((file, file_line)) => text += &format!("\n  --> {}:{}:{}", file, file_line, location.line_position),
+                None => text += &format!("\n  --> {}:?", self.name),
+            }
+            text += &format!("\n   | {}", code);
+            if !label.is_empty() {
+                let indent = " ".repeat(location.line_position.saturating_sub(1) as usize);
+                text += &format!("\n   | {}^ {}", indent, label);
+            }
+        }
+
+        anyhow!(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(shader: &Shader) -> Vec<&str> {
+        shader.source.lines().collect()
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let source = "#ifdef A\na\n#ifdef B\nab\n#else\na not b\n#endif\n#else\nnot a\n#ifndef B\nneither\n#endif\n#endif\nall";
+
+        let shader = Preprocessor::new().define("A", "").process("test", source).unwrap();
+        assert_eq!(lines(&shader), ["a", "a not b", "all"]);
+
+        let shader = Preprocessor::new().define("A", "").define("B", "").process("test", source).unwrap();
+        assert_eq!(lines(&shader), ["a", "ab", "all"]);
+
+        let shader = Preprocessor::new().process("test", source).unwrap();
+        assert_eq!(lines(&shader), ["not a", "neither", "all"]);
+    }
+
+    #[test]
+    fn defines_in_skipped_lines_are_ignored() {
+        let source = "#ifdef A\n#define B\n#endif\n#ifdef B\nb\n#endif";
+        let shader = Preprocessor::new().process("test", source).unwrap();
+        assert!(shader.source.is_empty());
+    }
+
+    #[test]
+    fn unbalanced_conditions() {
+        let e = Preprocessor::new().process("test", "x\n#ifdef A\n#ifdef B\n#endif").unwrap_err();
+        assert_eq!(e.to_string(), "test:2: #ifdef without #endif");
+
+        let e = Preprocessor::new().process("test", "#endif").unwrap_err();
+        assert!(e.to_string().contains("#endif without #ifdef"));
+
+        let e = Preprocessor::new().process("test", "#ifdef A\n#else\n#else\n#endif").unwrap_err();
+        assert!(e.to_string().starts_with("test:3: second #else"));
+    }
+
+    #[test]
+    fn substitutes_whole_names_outside_comments() {
+        let source = "#define SIZE 4.0\nlet x = SIZE * SIZE_2; // SIZE\nlet y = 1e5 + SIZE;";
+        let shader = Preprocessor::new().define("e5", "bad").process("test", source).unwrap();
+        assert_eq!(lines(&shader), ["let x = 4.0 * SIZE_2; // SIZE", "let y = 1e5 + 4.0;"]);
+    }
+
+    #[test]
+    fn undef_and_empty_defines() {
+        let source = "#define A\n#ifdef A\na\n#endif\n#undef A\n#ifdef A\nstill a\n#endif\nA";
+        let shader = Preprocessor::new().process("test", source).unwrap();
+        assert_eq!(lines(&shader), ["a", "A"]);
+    }
+
+    #[test]
+    fn includes_once() {
+        let shader = Preprocessor::new()
+            .module("common", "#include \"common\"\ncommon")
+            .module("a", "#include \"common\"\na")
+            .process("main", "#include \"a\"\n#include \"common\"\nmain")
+            .unwrap();
+        assert_eq!(lines(&shader), ["common", "a", "main"]);
+
+        let e = Preprocessor::new().process("main", "x\n#include \"missing\"").unwrap_err();
+        assert!(e.to_string().starts_with("main:2: no shader file missing"));
+
+        let e = Preprocessor::new().process("main", "#include missing").unwrap_err();
+        assert!(e.to_string().contains("quoted file name"));
+    }
+
+    #[test]
+    fn origin_follows_includes() {
+        let shader = Preprocessor::new()
+            .module("inner", "// inner\ninner")
+            .process("main", "first\n#include \"inner\"\n#ifdef A\nskipped\n#endif\nlast")
+            .unwrap();
+
+        assert_eq!(lines(&shader), ["first", "// inner", "inner", "last"]);
+        assert_eq!(shader.origin(1), Some(("main", 1)));
+        assert_eq!(shader.origin(2), Some(("inner", 1)));
+        assert_eq!(shader.origin(3), Some(("inner", 2)));
+        assert_eq!(shader.origin(4), Some(("main", 6)));
+        assert_eq!(shader.origin(0), None);
+        assert_eq!(shader.origin(5), None);
+    }
+}
@@ -0,0 +1,19 @@
+// The camera every world space pipeline has at group 1.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    pos: vec2<f32>,
+}
+
+@group(1)@binding(0)
+var<uniform> camera: CameraUniform;
+
+// Moves with the camera, for everything but ui
+fn world_to_clip(position: vec3<f32>) -> vec4<f32> {
+    return camera.view_proj * vec4(position - vec3(camera.pos, 0.0), 1.0);
+}
+
+// Ignores the camera's position, for ui
+fn screen_to_clip(position: vec3<f32>) -> vec4<f32> {
+    return camera.view_proj * vec4(position, 1.0);
+}
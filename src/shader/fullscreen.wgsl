@@ -0,0 +1,17 @@
+// A triangle covering the whole target, draw with 3 vertices and no vertex buffer.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    // counter clockwise, so it survives back face culling
+    let x = f32(index & 2u);
+    let y = f32((index << 1u) & 2u);
+    out.clip_position = vec4(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.tex_coords = vec2(x, y);
+    return out;
+}
@@ -0,0 +1,50 @@
+// Vertex stage and textures of sprites drawn from a texture array, see sprite.rs.
+
+#include "shader/camera.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) layer: u32,
+}
+
+@vertex
+fn vs_main (in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    // define SCREEN_SPACE for sprites that stay put when the camera moves, like ui images
+#ifdef SCREEN_SPACE
+    out.clip_position = screen_to_clip(in.position);
+#else
+    out.clip_position = world_to_clip(in.position);
+#endif
+    out.tex_coords = in.tex_coords;
+    out.layer = in.layer;
+
+    return out;
+}
+
+@group(0)@binding(0)
+var t_layers: texture_2d_array<f32>;
+@group(0)@binding(1)
+var t_sampler: sampler;
+
+// The sprite's texel at uv, in the sprite's own layer
+fn sprite_sample(in: VertexOutput, uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_layers, t_sampler, uv, i32(in.layer));
+}
+
+// What the sprite would have drawn without a material
+fn base_color(in: VertexOutput) -> vec4<f32> {
+    return sprite_sample(in, in.tex_coords);
+}
+
+// Size of one texel of the sprite textures in uv units
+fn sprite_texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_layers).xy);
+}
@@ -0,0 +1,325 @@
+/*
+Filled and stroked vector shapes, tessellated into triangles with lyon.
+
+Paths come from the helpers here or lyon's Path::builder() for anything else,
+beziers included. A ShapeMesh collects fills and strokes at one depth, later
+ones on top, and is drawn as a component like the ui text:
+
+    let mut bar = shape::ShapeMesh::new(0.5);
+    bar.fill(&shape::rounded_rect([10.0, 10.0], [110.0, 24.0], 4.0), [0.8, 0.1, 0.1, 1.0])?
+        .stroke(&shape::rounded_rect([10.0, 10.0], [110.0, 24.0], 4.0), &shape::Stroke::new(2.0), [0.0, 0.0, 0.0, 1.0])?;
+    state.world.spawn((bar,));
+    state.shapes_changed = true;
+
+Edges are antialiased without MSAA: every vertex carries how far it is from
+the edge of the shape, and coverage fades out over the last FEATHER units.
+Strokes are widened by FEATHER for it, fills get a FEATHER wide stroke along
+their outline fading from inside to outside.
+*/
+
+use anyhow::Result;
+use lyon::algorithms::area::approximate_signed_area;
+use lyon::math::{point, Box2D};
+use lyon::path::{builder::BorderRadii, PathEvent, Polygon, Winding};
+use lyon::tessellation::{
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, StrokeOptions, StrokeTessellator, StrokeVertex, VertexBuffers,
+};
+use wgpu::util::DeviceExt;
+
+use crate::blend;
+use crate::stats;
+use crate::text;
+
+pub use lyon::path::Path;
+pub use lyon::tessellation::{LineCap, LineJoin};
+
+/// Shapes need blending for their edges, so they only have the one pipeline.
+pub const BLEND_MODE: blend::BlendMode = blend::BlendMode::Alpha;
+
+/// Width of the antialiased edge, in the same units as the shapes.
+const FEATHER: f32 = 1.0;
+/// How far curves may be from the real ones once flattened.
+const TOLERANCE: f32 = 0.05;
+
+pub fn rounded_rect(min: [f32; 2], max: [f32; 2], radius: f32) -> Path {
+    let mut builder = Path::builder();
+    builder.add_rounded_rectangle(
+        &Box2D::new(point(min[0], min[1]), point(max[0], max[1])),
+        &BorderRadii::new(radius),
+        Winding::Positive,
+    );
+    builder.build()
+}
+
+pub fn circle(center: [f32; 2], radius: f32) -> Path {
+    let mut builder = Path::builder();
+    builder.add_circle(point(center[0], center[1]), radius, Winding::Positive);
+    builder.build()
+}
+
+/// Closed, the last point joins back to the first.
+pub fn polygon(points: &[[f32; 2]]) -> Path {
+    poly(points, true)
+}
+
+/// Open, for strokes.
+pub fn polyline(points: &[[f32; 2]]) -> Path {
+    poly(points, false)
+}
+
+fn poly(points: &[[f32; 2]], closed: bool) -> Path {
+    let points = points.iter().map(|p| point(p[0], p[1])).collect::<Vec<_>>();
+
+    let mut builder = Path::builder();
+    if points.len() > 1 {
+        builder.add_polygon(Polygon { points: &points, closed });
+    }
+    builder.build()
+}
+
+/// A cubic bezier from from to to, pulled towards the two control points.
+pub fn cubic(from: [f32; 2], control_1: [f32; 2], control_2: [f32; 2], to: [f32; 2]) -> Path {
+    let mut builder = Path::builder();
+    builder.begin(point(from[0], from[1]));
+    builder.cubic_bezier_to(point(control_1[0], control_1[1]), point(control_2[0], control_2[1]), point(to[0], to[1]));
+    builder.end(false);
+    builder.build()
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Stroke {
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+}
+
+impl Stroke {
+    /// Mitred joins and butt caps.
+    pub fn new(width: f32) -> Self {
+        Self { width, join: LineJoin::Miter, cap: LineCap::Butt }
+    }
+
+    pub fn join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    pub fn cap(mut self, cap: LineCap) -> Self {
+        self.cap = cap;
+        self
+    }
+}
+
+/// edge is (limit, distance), the coverage is limit - |distance| clamped to 0..1,
+/// interpolated across the triangle so it fades towards the outline.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShapeVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub edge: [f32; 2],
+}
+
+impl ShapeVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ShapeVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ]
+        }
+    }
+}
+
+/// Tessellated shapes at one depth, within -1..1 and higher values in front.
+#[derive(Clone, Debug, Default)]
+pub struct ShapeMesh {
+    pub vertices: Vec<ShapeVertex>,
+    pub indices: Vec<u32>,
+    pub z: f32,
+}
+
+impl ShapeMesh {
+    pub fn new(z: f32) -> Self {
+        Self { z, ..Self::default() }
+    }
+
+    /// Fills the inside of the path by the non zero rule, open sub paths are closed for it.
+    /// Holes have to be wound against the outline around them for their edges to fade the right way.
+    pub fn fill(&mut self, path: &Path, color: [f32; 4]) -> Result<&mut Self> {
+        let z = self.z;
+        let mut buffers = VertexBuffers::new();
+
+        let options = FillOptions::tolerance(TOLERANCE).with_fill_rule(lyon::path::FillRule::NonZero);
+        FillTessellator::new().tessellate_path(path, &options, &mut BuffersBuilder::new(&mut buffers, |vertex: FillVertex| ShapeVertex {
+            position: [vertex.position().x, vertex.position().y, z],
+            color,
+            edge: [1.0, 0.0],
+        }))?;
+
+        // fully covered on the inside of the outline and not at all outside. The inner half
+        // overlaps the fill, so translucent fills come out a little stronger along their edges
+        let closed = path.iter()
+            .map(|event| match event {
+                PathEvent::End { last, first, .. } => PathEvent::End { last, first, close: true },
+                event => event,
+            })
+            .collect::<Vec<_>>();
+
+        // y is up so the positive side is the left one, and the left of every edge is the filled
+        // side of a path whose outlines go counter clockwise with holes the other way round
+        let counter_clockwise = approximate_signed_area(TOLERANCE, closed.iter().copied()) > 0.0;
+        let options = StrokeOptions::tolerance(TOLERANCE).with_line_width(FEATHER);
+        StrokeTessellator::new().tessellate(closed.iter().copied(), &options, &mut BuffersBuilder::new(&mut buffers, |vertex: StrokeVertex| {
+            let inside = vertex.side().is_positive() == counter_clockwise;
+            ShapeVertex {
+                position: [vertex.position().x, vertex.position().y, z],
+                color,
+                edge: [1.0, if inside { 0.0 } else { 1.0 }],
+            }
+        }))?;
+
+        self.append(buffers);
+        Ok(self)
+    }
+
+    pub fn stroke(&mut self, path: &Path, stroke: &Stroke, color: [f32; 4]) -> Result<&mut Self> {
+        let z = self.z;
+        let mut buffers = VertexBuffers::new();
+
+        // from limit at the centre of the line to 0 at its widened sides
+        let limit = (stroke.width + FEATHER) / 2.0 / FEATHER;
+        let options = StrokeOptions::tolerance(TOLERANCE)
+            .with_line_width(stroke.width + FEATHER)
+            .with_line_join(stroke.join)
+            .with_line_cap(stroke.cap);
+        StrokeTessellator::new().tessellate_path(path, &options, &mut BuffersBuilder::new(&mut buffers, |vertex: StrokeVertex| ShapeVertex {
+            position: [vertex.position().x, vertex.position().y, z],
+            color,
+            edge: [limit, if vertex.side().is_positive() { limit } else { -limit }],
+        }))?;
+
+        self.append(buffers);
+        Ok(self)
+    }
+
+    /// Appends another mesh, keeping its own depth.
+    pub fn extend(&mut self, other: &ShapeMesh) {
+        let base = self.vertices.len() as u32;
+        self.indices.extend(other.indices.iter().map(|i| i + base));
+        self.vertices.extend_from_slice(&other.vertices);
+    }
+
+    fn append(&mut self, buffers: VertexBuffers<ShapeVertex, u32>) {
+        let base = self.vertices.len() as u32;
+
+        // lyon's triangles come in either winding, back face culling wants them counter clockwise
+        for triangle in buffers.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| buffers.vertices[triangle[i] as usize].position);
+            let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            let triangle = if cross < 0.0 { [triangle[0], triangle[2], triangle[1]] } else { [triangle[0], triangle[1], triangle[2]] };
+            self.indices.extend(triangle.map(|i| i + base));
+        }
+        self.vertices.extend(buffers.vertices);
+    }
+
+    pub fn to_buffers(&self, device: &wgpu::Device) -> text::TextBuffers {
+        let vertices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Shape Vertex Buffer"),
+                contents: bytemuck::cast_slice(&self.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
+        let indices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Shape Index Buffer"),
+                contents: bytemuck::cast_slice(&self.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+
+        stats::count_upload(std::mem::size_of_val(&self.vertices[..]) as u64);
+        stats::count_upload(std::mem::size_of_val(&self.indices[..]) as u64);
+
+        text::TextBuffers {
+            vertices,
+            indices,
+            length: self.indices.len() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // for each vertex off the outline, whether it is faded out and whether it lies outside the
+    // shape. The fill's own vertices are on the outline, the feather's half its width either side
+    fn feather(path: &Path, outside: impl Fn(f32, f32) -> Option<bool>) -> Vec<(bool, bool)> {
+        let mut mesh = ShapeMesh::new(0.0);
+        mesh.fill(path, [1.0; 4]).unwrap();
+
+        mesh.vertices.iter()
+            .filter_map(|vertex| {
+                let outside = outside(vertex.position[0], vertex.position[1])?;
+                Some((vertex.edge[1] == 1.0, outside))
+            })
+            .collect()
+    }
+
+    // None on the edge of the square
+    fn outside_square(x: f32, y: f32, min: f32, max: f32) -> Option<bool> {
+        let on_edge = ((x == min || x == max) && (min..=max).contains(&y)) || ((y == min || y == max) && (min..=max).contains(&x));
+        (!on_edge).then_some(x < min || x > max || y < min || y > max)
+    }
+
+    #[test]
+    fn fill_feathers_outwards_either_winding() {
+        let square = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let mut reversed = square;
+        reversed.reverse();
+
+        for points in [square, reversed] {
+            let feather = feather(&polygon(&points), |x, y| outside_square(x, y, 0.0, 10.0));
+            assert!(feather.iter().any(|(_, outside)| *outside) && feather.iter().any(|(_, outside)| !outside));
+            for (faded, outside) in feather {
+                assert_eq!(faded, outside);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_feathers_into_holes() {
+        // the hole is wound the other way, as the non zero rule needs
+        let mut builder = Path::builder();
+        builder.add_polygon(Polygon { points: &[point(0.0, 0.0), point(10.0, 0.0), point(10.0, 10.0), point(0.0, 10.0)], closed: true });
+        builder.add_polygon(Polygon { points: &[point(3.0, 3.0), point(3.0, 7.0), point(7.0, 7.0), point(7.0, 3.0)], closed: true });
+        let ring = builder.build();
+
+        let feather = feather(&ring, |x, y| {
+            let outer = outside_square(x, y, 0.0, 10.0)?;
+            let hole = outside_square(x, y, 3.0, 7.0)?;
+            Some(outer || !hole)
+        });
+        assert!(feather.iter().filter(|(_, outside)| *outside).count() > 8);
+        for (faded, outside) in feather {
+            assert_eq!(faded, outside);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// Vector shapes, see shape.rs. Coverage fades out towards the outline for antialiasing.
+
+#include "shader/camera.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) edge: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) edge: vec2<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+#ifdef SCREEN_SPACE
+    out.clip_position = screen_to_clip(in.position);
+#else
+    out.clip_position = world_to_clip(in.position);
+#endif
+    out.color = in.color;
+    out.edge = in.edge;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = clamp(in.edge.x - abs(in.edge.y), 0.0, 1.0);
+
+    return vec4(in.color.rgb, in.color.a * coverage);
+}
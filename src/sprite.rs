@@ -0,0 +1,136 @@
+/*
+Sprites drawn from a texture array: every texture in the array is the same
+size and each vertex carries the layer it samples, so sprites using any of
+them go out in a single draw call with a single bind group.
+*/
+
+use wgpu::util::DeviceExt;
+
+use crate::stats;
+use crate::text;
+use crate::texture;
+
+/// A sprite component, position is the top left corner like CharacterQuad.
+#[derive(Copy, Clone, Debug)]
+pub struct SpriteQuad {
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    pub uv: texture::UvRect,
+    pub layer: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub layer: u32,
+}
+
+impl SpriteVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SpriteVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
+                }
+            ]
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SpriteVecs {
+    pub vertices: Vec<SpriteVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl SpriteVecs {
+    pub fn from_quads<'a>(quads: impl IntoIterator<Item = &'a SpriteQuad>) -> Self {
+        let mut vecs = Self::default();
+
+        for quad in quads {
+            // reuse the text quad layout so winding and uv orientation match
+            let rect = text::TextVecs::from_rect(quad.position, quad.size, quad.uv, None);
+            let base = vecs.vertices.len() as u32;
+
+            vecs.vertices.extend(rect.vertices.iter().map(|v| SpriteVertex {
+                position: v.position,
+                tex_coords: v.tex_coords,
+                layer: quad.layer,
+            }));
+            vecs.indices.extend(rect.indices.iter().map(|i| i + base));
+        }
+
+        vecs
+    }
+
+    pub fn to_buffers(&self, device: &wgpu::Device) -> text::TextBuffers {
+        let length = self.indices.len() as u32;
+
+        let vertices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite Vertex Buffer"),
+                contents: bytemuck::cast_slice(&self.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
+
+        let indices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite Index Buffer"),
+                contents: bytemuck::cast_slice(&self.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+
+        stats::count_upload(std::mem::size_of_val(&self.vertices[..]) as u64);
+        stats::count_upload(std::mem::size_of_val(&self.indices[..]) as u64);
+
+        text::TextBuffers {
+            vertices,
+            indices,
+            length,
+        }
+    }
+}
+
+pub fn create_array_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(
+        &wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sprite_array_bind_group_layout"),
+        }
+    )
+}
@@ -0,0 +1,12 @@
+#include "shader/sprite.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = base_color(in);
+
+    if (color.a == 0.0) {
+        discard;
+    }
+
+    return color;
+}
@@ -0,0 +1,276 @@
+/*
+Sprite sheets exported by TexturePacker or Aseprite as JSON, in either the
+"hash" layout (frames keyed by name) or the "array" layout (frames listed with
+a filename field). Aseprite's frame tags come through as named animations.
+
+Trimmed sprites keep their original size and the offset of the trimmed
+rectangle inside it, so placing a frame by its untrimmed box lines up with
+the untrimmed art.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::text;
+use crate::texture;
+
+#[derive(Deserialize)]
+struct JsonRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct JsonSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame {
+    #[serde(default)]
+    filename: Option<String>,
+    frame: JsonRect,
+    #[serde(default)]
+    rotated: bool,
+    #[serde(default)]
+    trimmed: bool,
+    sprite_source_size: Option<JsonRect>,
+    source_size: Option<JsonSize>,
+    pivot: Option<JsonPoint>,
+    duration: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonFrames {
+    Hash(IndexMap<String, JsonFrame>),
+    Array(Vec<JsonFrame>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonMeta {
+    image: String,
+    size: Option<JsonSize>,
+    #[serde(default)]
+    frame_tags: Vec<JsonTag>,
+}
+
+#[derive(Deserialize)]
+struct JsonSheet {
+    frames: JsonFrames,
+    meta: JsonMeta,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    /// uv of each corner of the upright sprite: top left, bottom left, bottom right, top right
+    pub corners: [[f32; 2]; 4],
+    pub rotated: bool,
+    /// Size of the sprite before trimming
+    pub source_size: [f32; 2],
+    /// Where the trimmed rectangle sits inside source_size, from the top left
+    pub trim_offset: [f32; 2],
+    pub trim_size: [f32; 2],
+    /// Normalised within source_size, (0, 0) is top left
+    pub pivot: [f32; 2],
+    pub duration_ms: Option<u32>,
+}
+
+impl Frame {
+    /// Quad for this frame with its pivot at anchor, scale is world units per sheet pixel.
+    pub fn vecs_at(&self, anchor: [f32; 3], scale: f32, offset: Option<u32>) -> text::TextVecs {
+        let top_left = [
+            anchor[0] - self.pivot[0] * self.source_size[0] * scale,
+            anchor[1] + self.pivot[1] * self.source_size[1] * scale,
+        ];
+        let position = [
+            top_left[0] + self.trim_offset[0] * scale,
+            top_left[1] - self.trim_offset[1] * scale,
+            anchor[2],
+        ];
+        let size = [self.trim_size[0] * scale, self.trim_size[1] * scale];
+
+        text::TextVecs::from_corners(position, size, self.corners, offset)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+#[derive(Clone, Debug)]
+pub struct Animation {
+    /// Frame names in sheet order, from the tag's first to last frame
+    pub frames: Vec<String>,
+    pub direction: Direction,
+}
+
+pub struct SpriteSheet {
+    pub texture: texture::Texture,
+    pub frames: HashMap<String, Frame>,
+    /// Frame names in the order the file lists them
+    pub frame_order: Vec<String>,
+    pub animations: HashMap<String, Animation>,
+}
+
+impl SpriteSheet {
+    pub fn frame(&self, name: &str) -> Option<&Frame> {
+        self.frames.get(name)
+    }
+}
+
+/// The parsed json, without the texture, which resources::load_sprite_sheet loads from `image`.
+pub struct SheetDescription {
+    pub image: String,
+    pub frames: HashMap<String, Frame>,
+    pub frame_order: Vec<String>,
+    pub animations: HashMap<String, Animation>,
+}
+
+impl SheetDescription {
+    /// texture_size is only used if the json has no meta.size.
+    pub fn parse(json: &[u8], texture_size: Option<(u32, u32)>) -> Result<Self> {
+        let sheet: JsonSheet = serde_json::from_slice(json)?;
+
+        let (tw, th) = match (&sheet.meta.size, texture_size) {
+            (Some(size), _) => (size.w as f32, size.h as f32),
+            (None, Some(size)) => (size.0 as f32, size.1 as f32),
+            (None, None) => bail!("sprite sheet has no meta.size"),
+        };
+
+        let frames = match sheet.frames {
+            JsonFrames::Hash(frames) => frames.into_iter().collect::<Vec<_>>(),
+            JsonFrames::Array(frames) => frames.into_iter()
+                .enumerate()
+                .map(|(i, frame)| (frame.filename.clone().unwrap_or_else(|| i.to_string()), frame))
+                .collect(),
+        };
+
+        let mut result = Self {
+            image: sheet.meta.image,
+            frames: HashMap::new(),
+            frame_order: Vec::new(),
+            animations: HashMap::new(),
+        };
+
+        for (name, frame) in frames {
+            let r = &frame.frame;
+
+            // rotated frames are stored turned 90 degrees clockwise, so the sheet rect is h by w
+            let (sw, sh) = if frame.rotated { (r.h, r.w) } else { (r.w, r.h) };
+            let u0 = r.x as f32 / tw;
+            let v0 = r.y as f32 / th;
+            let u1 = (r.x + sw) as f32 / tw;
+            let v1 = (r.y + sh) as f32 / th;
+
+            let corners = if frame.rotated {
+                [[u1, v0], [u0, v0], [u0, v1], [u1, v1]]
+            } else {
+                [[u0, v0], [u0, v1], [u1, v1], [u1, v0]]
+            };
+
+            let (trim_offset, trim_size) = match (&frame.sprite_source_size, frame.trimmed) {
+                (Some(s), true) => ([s.x as f32, s.y as f32], [s.w as f32, s.h as f32]),
+                _ => ([0.0, 0.0], [r.w as f32, r.h as f32]),
+            };
+            let source_size = match &frame.source_size {
+                Some(s) => [s.w as f32, s.h as f32],
+                None => trim_size,
+            };
+            let pivot = frame.pivot.as_ref().map_or([0.0, 0.0], |p| [p.x, p.y]);
+
+            result.frame_order.push(name.clone());
+            result.frames.insert(name, Frame {
+                corners,
+                rotated: frame.rotated,
+                source_size,
+                trim_offset,
+                trim_size,
+                pivot,
+                duration_ms: frame.duration,
+            });
+        }
+
+        for tag in sheet.meta.frame_tags {
+            ensure!(
+                tag.from <= tag.to && tag.to < result.frame_order.len(),
+                "frame tag {} is out of range", tag.name
+            );
+
+            let direction = match tag.direction.as_deref() {
+                None | Some("forward") => Direction::Forward,
+                Some("reverse") => Direction::Reverse,
+                Some("pingpong") => Direction::PingPong,
+                Some(other) => bail!("unknown direction {} on tag {}", other, tag.name),
+            };
+
+            result.animations.insert(tag.name, Animation {
+                frames: result.frame_order[tag.from..=tag.to].to_vec(),
+                direction,
+            });
+        }
+
+        Ok(result)
+    }
+
+    pub fn with_texture(self, texture: texture::Texture) -> SpriteSheet {
+        SpriteSheet {
+            texture,
+            frames: self.frames,
+            frame_order: self.frame_order,
+            animations: self.animations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_frames_keep_file_order() {
+        // names sort as x 0, x 1, x 10, x 11, x 2 ... but tags index the file order
+        let frames = (0..12)
+            .map(|i| format!(r#""x {}.ase": {{ "frame": {{ "x": {}, "y": 0, "w": 8, "h": 8 }}, "duration": 100 }}"#, i, i * 8))
+            .collect::<Vec<_>>()
+            .join(",");
+        let json = format!(
+            r#"{{ "frames": {{ {} }}, "meta": {{ "image": "x.png", "size": {{ "w": 96, "h": 8 }},
+                "frameTags": [{{ "name": "tail", "from": 9, "to": 11, "direction": "forward" }}] }} }}"#,
+            frames
+        );
+        let sheet = SheetDescription::parse(json.as_bytes(), None).unwrap();
+
+        assert_eq!(sheet.frame_order[2], "x 2.ase");
+        assert_eq!(sheet.frame_order[10], "x 10.ase");
+        assert_eq!(sheet.animations["tail"].frames, ["x 9.ase", "x 10.ase", "x 11.ase"]);
+        assert_eq!(sheet.frames["x 10.ase"].corners[0], [80.0 / 96.0, 0.0]);
+    }
+}
@@ -0,0 +1,417 @@
+/*
+Render statistics, shown over the frame with F3.
+
+Anything that draws or uploads to a buffer reports it with count_draw (the
+indices drawn, or vertices for draws without an index buffer) and
+count_upload. The counts are global so they don't have to be passed around,
+Stats::frame takes them at the start of each update so what is shown is the
+frame before. Texture memory is added up from what is holding textures rather
+than counted as they come and go.
+
+GPU times come from timestamps written before and after each render graph
+pass, on adapters that can. They are read back a frame or more later rather
+than waiting for the GPU to catch up.
+*/
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use crate::text;
+
+static DRAW_CALLS: AtomicU64 = AtomicU64::new(0);
+static INDICES: AtomicU64 = AtomicU64::new(0);
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub fn count_draw(indices: u32) {
+    DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+    INDICES.fetch_add(indices as u64, Ordering::Relaxed);
+}
+
+pub fn count_upload(bytes: u64) {
+    UPLOADS.fetch_add(1, Ordering::Relaxed);
+    UPLOAD_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Counters {
+    pub draw_calls: u64,
+    pub indices: u64,
+    pub uploads: u64,
+    pub upload_bytes: u64,
+}
+
+impl Counters {
+    /// Everything counted since the last take.
+    fn take() -> Self {
+        Self {
+            draw_calls: DRAW_CALLS.swap(0, Ordering::Relaxed),
+            indices: INDICES.swap(0, Ordering::Relaxed),
+            uploads: UPLOADS.swap(0, Ordering::Relaxed),
+            upload_bytes: UPLOAD_BYTES.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Roughly how much memory a texture takes up, every mip level and sample included.
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    // combined depth stencil formats have no single block size
+    let block_size = format.block_size(None).unwrap_or(4) as u64;
+    let size = texture.size();
+
+    let level_bytes = (0..texture.mip_level_count())
+        .map(|level| {
+            let width = (size.width >> level).max(1);
+            let height = (size.height >> level).max(1);
+            let blocks = width.div_ceil(block_width) as u64 * height.div_ceil(block_height) as u64;
+            blocks * block_size
+        })
+        .sum::<u64>();
+
+    level_bytes * size.depth_or_array_layers as u64 * texture.sample_count() as u64
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+pub struct Stats {
+    pub visible: bool,
+    // seconds, newest last
+    frame_times: VecDeque<f32>,
+    smoothed_frame_time: f32,
+    counters: Counters,
+    pub texture_memory: u64,
+    // milliseconds each pass took, None without timestamp support
+    pub gpu_times: Option<Vec<(String, f32)>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            frame_times: VecDeque::with_capacity(Self::HISTORY),
+            smoothed_frame_time: 0.0,
+            counters: Counters::default(),
+            texture_memory: 0,
+            gpu_times: None,
+        }
+    }
+}
+
+impl Stats {
+    /// Frames kept for the graph.
+    const HISTORY: usize = 120;
+    /// How much each new frame moves the smoothed frame time.
+    const SMOOTHING: f32 = 0.05;
+    /// The frame time the top of the graph stands for, in milliseconds.
+    const GRAPH_MAX: f32 = 1000.0 / 30.0;
+
+    pub fn frame(&mut self, dt: time::Duration) {
+        let dt = dt.as_secs_f32();
+
+        self.smoothed_frame_time = if self.frame_times.is_empty() {
+            dt
+        } else {
+            self.smoothed_frame_time + (dt - self.smoothed_frame_time) * Self::SMOOTHING
+        };
+
+        if self.frame_times.len() == Self::HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+
+        self.counters = Counters::take();
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.smoothed_frame_time > 0.0 { 1.0 / self.smoothed_frame_time } else { 0.0 }
+    }
+
+    /// Shortest, average and longest frame of the last HISTORY, in milliseconds.
+    pub fn frame_time_range(&self) -> (f32, f32, f32) {
+        if self.frame_times.is_empty() {
+            return (0.0, 0.0, 0.0);
+        }
+
+        let min = self.frame_times.iter().copied().fold(f32::MAX, f32::min);
+        let max = self.frame_times.iter().copied().fold(0.0, f32::max);
+        let average = self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32;
+
+        (min * 1000.0, average * 1000.0, max * 1000.0)
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let (min, average, max) = self.frame_time_range();
+        let counters = self.counters;
+
+        let mut lines = vec![
+            format!("fps {:.1} ({:.2} ms)", self.fps(), self.smoothed_frame_time * 1000.0),
+            format!("frame ms min {:.2} avg {:.2} max {:.2}", min, average, max),
+            format!("draws {} indices {}", counters.draw_calls, counters.indices),
+            format!("uploads {} ({:.1} KB)", counters.uploads, counters.upload_bytes as f64 / 1024.0),
+            format!("textures {:.1} MB", megabytes(self.texture_memory)),
+        ];
+
+        match &self.gpu_times {
+            Some(times) => {
+                let total = times.iter().map(|(_, ms)| ms).sum::<f32>();
+                lines.push(format!("gpu {:.2} ms", total));
+                lines.extend(times.iter().map(|(name, ms)| format!("  {} {:.2}", name, ms)));
+            },
+            None => lines.push("gpu times not supported".to_string()),
+        }
+
+        lines
+    }
+
+    /// The lines and a frame time graph in the top right corner of a screen this size, as
+    /// quads for the ui font texture. Returns the quad count too, for batching.
+    pub fn overlay(&self, width: f32, height: f32) -> (text::TextVecs, usize) {
+        let size = 12.0;
+        // in front of everything
+        let z = 0.9;
+
+        let lines = self.lines();
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0).max(Self::HISTORY * 2 / size as usize);
+        let left = width - size * (columns as f32 + 1.0);
+
+        let mut quads = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let y = height - size * (i as f32 + 1.0);
+            quads.extend(text::character_quads_from_str(line, vec![left, y, 0.0], size).into_iter().map(|mut quad| {
+                quad.position[2] = z;
+                quad
+            }));
+        }
+        let mut vecs = text::TextVecs::from_quads(&quads);
+        let mut count = quads.len();
+
+        let graph_height = size * 5.0;
+        let graph_bottom = height - size * (lines.len() as f32 + 1.0) - graph_height;
+
+        // one bar per frame, the oldest on the left
+        for (i, dt) in self.frame_times.iter().enumerate() {
+            let bar = (dt * 1000.0 / Self::GRAPH_MAX).min(1.0) * graph_height;
+            let x = left + i as f32 * 2.0;
+            vecs.extend(text::TextVecs::from_rect([x, graph_bottom + bar, z], [2.0, bar], text::SOLID_UV, None));
+            count += 1;
+        }
+
+        // a line where 60fps would be
+        let sixty = 1000.0 / 60.0 / Self::GRAPH_MAX * graph_height;
+        vecs.extend(text::TextVecs::from_rect([left, graph_bottom + sixty, z], [Self::HISTORY as f32 * 2.0, 1.0], text::SOLID_UV, None));
+        count += 1;
+
+        (vecs, count)
+    }
+}
+
+enum Readback {
+    Idle,
+    // the pass names of a frame whose timestamps are being copied out
+    Copied(Vec<String>),
+    Mapping(Vec<String>, Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>),
+}
+
+/// Times render graph passes on the GPU, see graph::RenderGraph::execute_timed.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // nanoseconds per timestamp tick
+    period: f32,
+    passes: Vec<String>,
+    readback: Readback,
+    times: Vec<(String, f32)>,
+}
+
+impl GpuTimer {
+    const MAX_PASSES: u32 = 32;
+
+    /// None when the device can't write timestamps.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: Self::MAX_PASSES * 2,
+        });
+        let size = (Self::MAX_PASSES * 2) as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            passes: Vec::new(),
+            readback: Readback::Idle,
+            times: Vec::new(),
+        })
+    }
+
+    /// Returns what to give end_pass, None once MAX_PASSES are being timed this frame.
+    pub fn begin_pass(&mut self, encoder: &mut wgpu::CommandEncoder, name: &str) -> Option<u32> {
+        let index = self.passes.len() as u32;
+        if index == Self::MAX_PASSES {
+            return None;
+        }
+
+        encoder.write_timestamp(&self.query_set, index * 2);
+        self.passes.push(name.to_string());
+        Some(index)
+    }
+
+    pub fn end_pass(&mut self, encoder: &mut wgpu::CommandEncoder, index: u32) {
+        encoder.write_timestamp(&self.query_set, index * 2 + 1);
+    }
+
+    /// Copies this frame's timestamps out, unless an earlier frame's are still being read.
+    pub fn finish(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = std::mem::take(&mut self.passes);
+        if passes.is_empty() || matches!(self.readback, Readback::Mapping(..)) {
+            return;
+        }
+
+        let count = passes.len() as u32 * 2;
+        let size = count as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, size);
+
+        self.readback = Readback::Copied(passes);
+    }
+
+    /// Call once the encoder finish was given to has been submitted.
+    pub fn submitted(&mut self) {
+        if let Readback::Copied(passes) = std::mem::replace(&mut self.readback, Readback::Idle) {
+            let size = passes.len() as wgpu::BufferAddress * 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+            let result = Arc::new(Mutex::new(None));
+            let sender = result.clone();
+
+            self.readback_buffer.slice(..size).map_async(wgpu::MapMode::Read, move |r| {
+                *sender.lock().unwrap() = Some(r);
+            });
+            self.readback = Readback::Mapping(passes, result);
+        }
+    }
+
+    /// Picks up timestamps that have been read back, without waiting for any that haven't.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+
+        let Readback::Mapping(passes, result) = &self.readback else { return };
+        let Some(result) = result.lock().unwrap().take() else { return };
+
+        if result.is_ok() {
+            let size = passes.len() as wgpu::BufferAddress * 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+            let data = self.readback_buffer.slice(..size).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+
+            self.times = passes.iter()
+                .zip(timestamps.chunks_exact(2))
+                .map(|(name, pair)| (name.clone(), pair[1].saturating_sub(pair[0]) as f32 * self.period / 1_000_000.0))
+                .collect();
+
+            drop(data);
+            self.readback_buffer.unmap();
+        }
+
+        self.readback = Readback::Idle;
+    }
+
+    /// Milliseconds each pass of the latest frame read back took.
+    pub fn times(&self) -> &[(String, f32)] {
+        &self.times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the counters are global, so tests that count or take them can't run at the same time
+    static COUNTERS: Mutex<()> = Mutex::new(());
+
+    fn ms(ms: u64) -> time::Duration {
+        time::Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn smoothing_starts_at_the_first_frame() {
+        let _counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats = Stats::default();
+        assert_eq!(stats.fps(), 0.0);
+
+        stats.frame(ms(10));
+        assert!((stats.fps() - 100.0).abs() < 1e-3);
+
+        // each frame moves it SMOOTHING of the way to the new time
+        stats.frame(ms(20));
+        assert!((stats.smoothed_frame_time - 0.0105).abs() < 1e-6);
+        for _ in 0..200 {
+            stats.frame(ms(20));
+        }
+        assert!((stats.fps() - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn range_covers_the_last_history_frames() {
+        let _counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats = Stats::default();
+        assert_eq!(stats.frame_time_range(), (0.0, 0.0, 0.0));
+
+        // the slow first frame falls out of the history
+        stats.frame(ms(100));
+        for i in 0..Stats::HISTORY {
+            stats.frame(ms(if i % 2 == 0 { 10 } else { 20 }));
+        }
+
+        let (min, average, max) = stats.frame_time_range();
+        assert!((min - 10.0).abs() < 1e-3);
+        assert!((average - 15.0).abs() < 1e-3);
+        assert!((max - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn counters_are_per_frame() {
+        let _counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats = Stats::default();
+        stats.frame(ms(16));
+
+        count_draw(6);
+        count_draw(30);
+        count_upload(64);
+        stats.frame(ms(16));
+
+        let counters = stats.counters();
+        assert_eq!((counters.draw_calls, counters.indices, counters.uploads, counters.upload_bytes), (2, 36, 1, 64));
+
+        // a frame without any starts again from nothing
+        stats.frame(ms(16));
+        let counters = stats.counters();
+        assert_eq!((counters.draw_calls, counters.indices, counters.uploads, counters.upload_bytes), (0, 0, 0, 0));
+    }
+}
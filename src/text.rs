@@ -1,9 +1,11 @@
+use crate::stats;
 use crate::texture;
 use wgpu::util::DeviceExt;
-use winit::dpi::Size;
-use std::collections::HashMap;
 
 
+/// The middle of the '|' in all_16x16.png, which is solid, for plain shapes drawn with the font.
+pub const SOLID_UV: texture::UvRect = texture::UvRect { min: [39.0 / 160.0, 146.0 / 160.0], max: [40.0 / 160.0, 157.0 / 160.0] };
+
 #[derive(Default, Debug)]
 pub struct TextVecs {
     pub vertices: Vec<CharacterVertex>,
@@ -18,30 +20,46 @@ pub struct TextBuffers {
 
 impl TextVecs {
     pub fn from_quad(quad: CharacterQuad, offset: Option<u32>) -> Self {
-        let offset: u32 = offset.unwrap_or(0);
         let scale: f32 = 1.0 / 10.0;
 
         let c_y = (quad.character / 10) as f32;
         let c_x = (quad.character % 10) as f32;
 
-        let tl = [c_x * scale, c_y * scale];
-        let bl = [c_x * scale, c_y * scale + scale];
-        let br = [c_x * scale + scale, c_y * scale + scale];
-        let tr = [c_x * scale + scale, c_y * scale];
+        let uv = texture::UvRect {
+            min: [c_x * scale, c_y * scale],
+            max: [c_x * scale + scale, c_y * scale + scale],
+        };
+
+        Self::from_rect(quad.position, quad.size, uv, offset)
+    }
 
-        let x_1 = quad.position[0];
-        let x_2 = quad.position[0] + quad.size[0];
-        let y_1 = quad.position[1];
-        let y_2 = quad.position[1] - quad.size[1];
+    /// A quad with its top left corner at position, showing the uv region of whatever texture is bound.
+    pub fn from_rect(position: [f32; 3], size: [f32; 2], uv: texture::UvRect, offset: Option<u32>) -> Self {
+        let corners = [uv.top_left(), uv.bottom_left(), uv.bottom_right(), uv.top_right()];
+        Self::from_corners(position, size, corners, offset)
+    }
+
+    /// Like from_rect but with each corner's uv given separately (top left, bottom left,
+    /// bottom right, top right), for regions stored rotated in their sheet.
+    pub fn from_corners(position: [f32; 3], size: [f32; 2], corners: [[f32; 2]; 4], offset: Option<u32>) -> Self {
+        let offset: u32 = offset.unwrap_or(0);
+
+        let [tl, bl, br, tr] = corners;
+
+        let x_1 = position[0];
+        let x_2 = position[0] + size[0];
+        let y_1 = position[1];
+        let y_2 = position[1] - size[1];
+        let z = position[2];
 
         let vertices = vec![
-            CharacterVertex { position: [x_1, y_1, 0.0], tex_coords: tl }, // TOP LEFT
-            CharacterVertex { position: [x_1, y_2, 0.0], tex_coords: bl }, // BOTTOM LEFT
-            CharacterVertex { position: [x_2, y_2, 0.0], tex_coords: br }, // BOTTOM RIGHT
-            CharacterVertex { position: [x_2, y_1, 0.0], tex_coords: tr }, // TOP RIGHT
+            CharacterVertex { position: [x_1, y_1, z], tex_coords: tl }, // TOP LEFT
+            CharacterVertex { position: [x_1, y_2, z], tex_coords: bl }, // BOTTOM LEFT
+            CharacterVertex { position: [x_2, y_2, z], tex_coords: br }, // BOTTOM RIGHT
+            CharacterVertex { position: [x_2, y_1, z], tex_coords: tr }, // TOP RIGHT
         ];
 
-        let indices = vec![
+        let indices = [
             1, 3, 0, 1, 2, 3,
             ].iter()
             .map(|x| x + (offset * 4))
@@ -54,10 +72,17 @@ impl TextVecs {
 
         //println!("\n{:?}\n", result);
 
-        return result;
+        result
+    }
+
+    /// Appends another set of vertices, shifting its indices past the ones already here.
+    pub fn extend(&mut self, mut other: TextVecs) {
+        let base = self.vertices.len() as u32;
+        self.indices.extend(other.indices.iter().map(|i| i + base));
+        self.vertices.append(&mut other.vertices);
     }
 
-    pub fn from_quads(quads: &Vec<CharacterQuad>) -> Self {
+    pub fn from_quads(quads: &[CharacterQuad]) -> Self {
         quads.iter()
             .fold((Self::default(), 0), |a, e| {
                 let mut tv = a.0;
@@ -91,6 +116,9 @@ impl TextVecs {
             }
         );
         
+        stats::count_upload(std::mem::size_of_val(&self.vertices[..]) as u64);
+        stats::count_upload(std::mem::size_of_val(&self.indices[..]) as u64);
+
         TextBuffers {
             vertices,
             indices,
@@ -179,7 +207,7 @@ pub fn character_quads_from_str(text: &str, pos: Vec<f32>, fsize: f32) -> Vec<Ch
         .collect()*/
 
     text.as_bytes()
-        .into_iter()
+        .iter()
         .enumerate()
         .map(|(i, c)| {
             let character = (*c as u32) - 32; // ascii offset for my texture
@@ -202,6 +230,7 @@ pub fn character_quads_from_str(text: &str, pos: Vec<f32>, fsize: f32) -> Vec<Ch
     //            .collect();
 }
 
+/// position[2] is the depth, within -1..1 and higher values in front.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CharacterQuad {
@@ -210,32 +239,6 @@ pub struct CharacterQuad {
     pub character: u32,
 }
 
-impl CharacterQuad {
-    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<CharacterVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Uint32,
-                }
-            ]
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CharacterVertex {
@@ -263,3 +266,20 @@ impl CharacterVertex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_layout_matches_the_struct() {
+        let desc = CharacterVertex::desc();
+
+        assert_eq!(desc.array_stride, std::mem::size_of::<CharacterVertex>() as wgpu::BufferAddress);
+        let offsets = desc.attributes.iter().map(|attribute| attribute.offset as usize).collect::<Vec<_>>();
+        assert_eq!(offsets, [std::mem::offset_of!(CharacterVertex, position), std::mem::offset_of!(CharacterVertex, tex_coords)]);
+        for attribute in desc.attributes {
+            assert!(attribute.offset + attribute.format.size() <= desc.array_stride);
+        }
+    }
+}
@@ -1,12 +1,6 @@
 // VERTEX
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-    pos: vec2<f32>,
-}
-
-@group(1)@binding(0)
-var<uniform> camera: CameraUniform;
+#include "shader/camera.wgsl"
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -21,11 +15,12 @@ struct VertexInput {
 @vertex
 fn vs_main (in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = (camera.view_proj * vec4(in.position - vec3(camera.pos, 0.0), 1.0));
-    // this one will move stuff basef on camera position, final use for all but ui
-
-    // out.clip_position = (camera.view_proj * vec4(in.position , 1.0));
-    // this one ignores camera position, final use for ui
+    // define SCREEN_SPACE for text that stays put when the camera moves
+#ifdef SCREEN_SPACE
+    out.clip_position = screen_to_clip(in.position);
+#else
+    out.clip_position = world_to_clip(in.position);
+#endif
 
     out.tex_coords = in.tex_coords;
 
@@ -43,6 +38,11 @@ var t_sampler: sampler;
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let color: vec4<f32> = textureSample(t_sheet, t_sampler, in.tex_coords);
 
+    // empty texels would otherwise still write depth and hide what is behind them
+    if (color.a == 0.0) {
+        discard;
+    }
+
     //let a: f32  = color.w;
 
     //var color_out: vec3<f32> = vec3(0.0, 0.0, 0.0);
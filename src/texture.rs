@@ -1,6 +1,10 @@
 use image::GenericImageView;
 use anyhow::*;
 
+use crate::compressed;
+use crate::mipmap;
+use crate::stats;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -10,21 +14,32 @@ pub struct Texture {
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    /// sample_count has to match the colour attachment it is used with.
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
+        Self::create_depth_texture_sized(device, config.width, config.height, sample_count, label)
+    }
+
+    pub fn create_depth_texture_sized(device: &wgpu::Device, width: u32, height: u32, sample_count: u32, label: &str) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
+        // multisampled depth is only ever an attachment, and some backends
+        // (GL at least) lose its contents when it is also made sampleable
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -48,24 +63,307 @@ impl Texture {
         Self { texture, view, sampler }
     }
 
+    /// A colour texture that can be rendered into and then sampled like any other texture.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            }
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
+    /// A multisampled colour texture to draw into and resolve from, it cannot be sampled directly.
+    pub fn create_multisampled_view(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> wgpu::TextureView {
+        device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            }
+        ).create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Copies the texture, or a rectangle of it, back from the GPU. Only works on
+    /// 8 bit RGBA/BGRA textures with COPY_SRC usage (images and render targets have it).
+    /// The copy is submitted straight away, the Readback finishes once the device has been
+    /// polled after the GPU is done with it, e.g. by `device.poll(wgpu::Maintain::Wait)`.
+    pub fn read_pixels<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+        rect: Option<PixelRect>,
+    ) -> Result<Readback<'a>> {
+        let format = self.texture.format();
+        let swizzle = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => bail!("cannot read back {:?} textures", format),
+        };
+        ensure!(
+            self.texture.usage().contains(wgpu::TextureUsages::COPY_SRC),
+            "texture was not created with COPY_SRC"
+        );
+
+        let rect = rect.unwrap_or(PixelRect { x: 0, y: 0, width: self.texture.width(), height: self.texture.height() });
+        ensure!(
+            rect.fits_in(self.texture.width(), self.texture.height()),
+            "{:?} is outside the texture", rect
+        );
+
+        // rows in the copy buffer have to start on 256 byte boundaries
+        let unpadded_bytes_per_row = rect.width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * rect.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: rect.x, y: rect.y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(rect.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: rect.width,
+                height: rect.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let mapped = std::sync::Arc::new(std::sync::Mutex::new(Mapping::default()));
+        let result = mapped.clone();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |r| {
+            let mut mapping = result.lock().unwrap();
+            mapping.result = Some(r);
+            if let Some(waker) = mapping.waker.take() {
+                waker.wake();
+            }
+        });
+
+        Ok(Readback { device, buffer, mapped, rect, padded_bytes_per_row, swizzle })
+    }
+
+    /// Magenta and black checker shown while the real texture is still loading.
+    pub fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let img = image::RgbaImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+
+        Self::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), Some("placeholder"))
+            .unwrap()
+    }
+
+    pub fn bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, label: &str) -> wgpu::BindGroup {
+        device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&self.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    }
+                ],
+                label: Some(label),
+            }
+        )
+    }
+
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
     ) -> Result<Self> {
+        if compressed::CompressedImage::is_container(bytes) {
+            let img = compressed::CompressedImage::parse(bytes)?;
+            return Self::from_compressed(device, queue, &img, Some(label), &TextureOptions::default());
+        }
+
         let img = image::load_from_memory(bytes)?;
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    /// Uploads a DDS/KTX2 image as is if the device supports BC formats (and it is not
+    /// being premultiplied), otherwise
+    /// decompresses it and goes through from_image_with_options. The file's own
+    /// colour space wins over the one in options.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &compressed::CompressedImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let color_space = if img.srgb { ColorSpace::Srgb } else { ColorSpace::Linear };
+        let options = options.color_space(color_space);
+
+        // premultiplying needs the pixels, so those go the decompressed route too
+        if !img.can_upload(device) || options.premultiply_alpha {
+            let rgba = image::DynamicImage::ImageRgba8(img.decompress()?);
+            return Self::from_image_with_options(device, queue, &rgba, label, &options);
+        }
+
+        let format = img.format.wgpu_format(img.srgb);
+        let size = wgpu::Extent3d {
+            width: img.width,
+            height: img.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: img.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            }
+        );
+
+        for (level, data) in img.levels.iter().enumerate() {
+            let width = (img.width >> level).max(1);
+            let height = (img.height >> level).max(1);
+
+            // copies of compressed textures are in whole blocks, even for the 2x2 and 1x1 levels
+            let physical = size.mip_level_size(level as u32, wgpu::TextureDimension::D2)
+                .physical_size(format);
+
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width.div_ceil(4) * img.format.block_size()),
+                    rows_per_image: Some(height.div_ceil(4)),
+                },
+                physical,
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&options.sampler_descriptor());
+
+        Ok(Self { texture, view, sampler })
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
+        Self::from_image_with_options(device, queue, img, label, &TextureOptions::default())
+    }
+
+    pub fn from_image_with_options(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let mut rgba = img.to_rgba8();
+        if options.premultiply_alpha {
+            premultiply_alpha(&mut rgba, options.color_space);
+        }
         let dimensions = img.dimensions();
+        let format = options.format();
+        let mipmaps = options.mipmaps;
+
+        let mip_level_count = match mipmaps {
+            Mipmaps::None => 1,
+            Mipmaps::Cpu | Mipmaps::Gpu => mipmap::mip_level_count(dimensions.0, dimensions.1),
+        };
+
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC;
+        if mipmaps == Mipmaps::Gpu {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -76,44 +374,726 @@ impl Texture {
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                format,
+                usage,
                 view_formats: &[],
             }
         );
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+        write_level(queue, &texture, 0, &rgba);
+
+        match mipmaps {
+            Mipmaps::None => {},
+            Mipmaps::Cpu => {
+                for (level, mip) in mipmap::generate_cpu(&rgba, mip_level_count, options.color_space).iter().enumerate() {
+                    write_level(queue, &texture, level as u32 + 1, mip);
+                }
             },
-            size,
-        );
+            Mipmaps::Gpu => mipmap::generate_gpu(device, queue, &texture, format, mip_level_count)?,
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Nearest,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
+        let sampler = device.create_sampler(&options.sampler_descriptor());
+
+        Ok(Self { texture, view, sampler })
+    }
+}
+
+/// Same sized images stacked as the layers of one D2Array texture, see sprite.rs.
+pub struct TextureArray {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+}
+
+impl TextureArray {
+    pub fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::RgbaImage],
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        ensure!(!images.is_empty(), "a texture array needs at least one image");
+
+        let (width, height) = images[0].dimensions();
+        for (i, img) in images.iter().enumerate() {
+            ensure!(
+                img.dimensions() == (width, height),
+                "layer {} is {:?} but the array is {:?}", i, img.dimensions(), (width, height)
+            );
+        }
+
+        let layers = images.len() as u32;
+        let max_layers = device.limits().max_texture_array_layers;
+        ensure!(layers <= max_layers, "{} layers is more than the device allows ({})", layers, max_layers);
+
+        let mip_level_count = match options.mipmaps {
+            Mipmaps::None => 1,
+            // GPU generation renders to one 2D view at a time, arrays get theirs on the CPU
+            Mipmaps::Cpu | Mipmaps::Gpu => mipmap::mip_level_count(width, height),
+        };
+
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: options.format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
             }
         );
 
-        Ok(Self { texture, view, sampler })
+        for (layer, img) in images.iter().enumerate() {
+            let img = if options.premultiply_alpha {
+                let mut copy = img.clone();
+                premultiply_alpha(&mut copy, options.color_space);
+                std::borrow::Cow::Owned(copy)
+            } else {
+                std::borrow::Cow::Borrowed(img)
+            };
+
+            write_layer(queue, &texture, 0, layer as u32, &img);
+
+            if mip_level_count > 1 {
+                for (level, mip) in mipmap::generate_cpu(&img, mip_level_count, options.color_space).iter().enumerate() {
+                    write_layer(queue, &texture, level as u32 + 1, layer as u32, mip);
+                }
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&options.sampler_descriptor());
+
+        Ok(Self { texture, view, sampler, width, height, layers })
+    }
+
+    pub fn bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, label: &str) -> wgpu::BindGroup {
+        device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&self.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    }
+                ],
+                label: Some(label),
+            }
+        )
+    }
+}
+
+/// Colour (and optionally depth) textures to render a camera into. With a sample_count
+/// above 1 drawing goes to a multisampled texture that resolves into color.
+pub struct RenderTarget {
+    pub color: Texture,
+    pub depth: Option<Texture>,
+    pub multisampled: Option<wgpu::TextureView>,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    label: String,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        with_depth: bool,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let color = Texture::create_render_target(device, width, height, format, label);
+        let depth = with_depth
+            .then(|| Texture::create_depth_texture_sized(device, width, height, sample_count, &format!("{} depth", label)));
+        let multisampled = (sample_count > 1)
+            .then(|| Texture::create_multisampled_view(device, width, height, format, sample_count, &format!("{} multisampled", label)));
+
+        Self { color, depth, multisampled, width, height, format, sample_count, label: label.to_string() }
+    }
+
+    /// Recreates the textures at a new size, bind groups made from the old ones need remaking.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width > 0 && height > 0 && (width, height) != (self.width, self.height) {
+            *self = Self::new(device, width, height, self.format, self.depth.is_some(), self.sample_count, &self.label);
+        }
+    }
+
+    pub fn color_attachment(&self, clear: wgpu::Color) -> wgpu::RenderPassColorAttachment<'_> {
+        color_attachment(&self.color.view, self.multisampled.as_ref(), clear)
+    }
+
+    /// Memory taken by the textures, the multisampled one counted from its description.
+    pub fn memory(&self) -> u64 {
+        let color = stats::texture_bytes(&self.color.texture);
+        let multisampled = if self.multisampled.is_some() { color * self.sample_count as u64 } else { 0 };
+        let depth = self.depth.as_ref().map_or(0, |depth| stats::texture_bytes(&depth.texture));
+
+        color + multisampled + depth
+    }
+
+    pub fn depth_attachment(&self) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        self.depth.as_ref().map(|depth| wgpu::RenderPassDepthStencilAttachment {
+            view: &depth.view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        })
+    }
+}
+
+/// Clears and draws into view, or into multisampled when there is one and resolves that into view.
+pub fn color_attachment<'a>(
+    view: &'a wgpu::TextureView,
+    multisampled: Option<&'a wgpu::TextureView>,
+    clear: wgpu::Color,
+) -> wgpu::RenderPassColorAttachment<'a> {
+    match multisampled {
+        Some(multisampled) => wgpu::RenderPassColorAttachment {
+            view: multisampled,
+            resolve_target: Some(view),
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear),
+                // only the resolved result is needed afterwards
+                store: false,
+            },
+        },
+        None => wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear),
+                store: true,
+            },
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    /// Whether the rectangle is non-empty and inside a texture of the given size.
+    pub fn fits_in(&self, width: u32, height: u32) -> bool {
+        self.width > 0 && self.height > 0
+            && self.x.checked_add(self.width).is_some_and(|right| right <= width)
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= height)
+    }
+}
+
+/// A copy made by Texture::read_pixels on its way back from the GPU. Each poll of the
+/// future polls the device without blocking, when the buffer isn't mapped yet it waits
+/// to be woken by the map callback, which runs from whatever polls the device next.
+pub struct Readback<'a> {
+    device: &'a wgpu::Device,
+    buffer: wgpu::Buffer,
+    mapped: std::sync::Arc<std::sync::Mutex<Mapping>>,
+    rect: PixelRect,
+    padded_bytes_per_row: u32,
+    swizzle: bool,
+}
+
+/// Shared between a Readback and its map callback.
+#[derive(Default)]
+struct Mapping {
+    result: Option<std::result::Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<std::task::Waker>,
+}
+
+impl Readback<'_> {
+    fn pixels(&self) -> Result<image::RgbaImage> {
+        let unpadded_bytes_per_row = self.rect.width * 4;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.rect.height) as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        self.buffer.unmap();
+
+        if self.swizzle {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(self.rect.width, self.rect.height, pixels)
+            .ok_or_else(|| anyhow!("readback buffer was the wrong size"))
+    }
+}
+
+impl std::future::Future for Readback<'_> {
+    type Output = Result<image::RgbaImage>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        self.device.poll(wgpu::Maintain::Poll);
+
+        let mut mapping = self.mapped.lock().unwrap();
+        match mapping.result.take() {
+            Some(result) => {
+                drop(mapping);
+                std::task::Poll::Ready(result.map_err(Error::from).and_then(|_| self.pixels()))
+            },
+            None => {
+                mapping.waker = Some(cx.waker().clone());
+                std::task::Poll::Pending
+            },
+        }
+    }
+}
+
+/// A region of a texture in normalised coordinates, v pointing down the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    pub const FULL: UvRect = UvRect { min: [0.0, 0.0], max: [1.0, 1.0] };
+
+    /// From a pixel rectangle inside a texture of the given size.
+    pub fn from_pixels(x: u32, y: u32, width: u32, height: u32, texture_size: (u32, u32)) -> Self {
+        let (tw, th) = (texture_size.0 as f32, texture_size.1 as f32);
+        Self {
+            min: [x as f32 / tw, y as f32 / th],
+            max: [(x + width) as f32 / tw, (y + height) as f32 / th],
+        }
+    }
+
+    pub fn top_left(&self) -> [f32; 2] {
+        self.min
+    }
+
+    pub fn bottom_left(&self) -> [f32; 2] {
+        [self.min[0], self.max[1]]
+    }
+
+    pub fn bottom_right(&self) -> [f32; 2] {
+        self.max
+    }
+
+    pub fn top_right(&self) -> [f32; 2] {
+        [self.max[0], self.min[1]]
+    }
+
+    /// A sub region, with min and max given as fractions of this one.
+    pub fn sub(&self, min: [f32; 2], max: [f32; 2]) -> Self {
+        let w = self.max[0] - self.min[0];
+        let h = self.max[1] - self.min[1];
+        Self {
+            min: [self.min[0] + min[0] * w, self.min[1] + min[1] * h],
+            max: [self.min[0] + max[0] * w, self.min[1] + max[1] * h],
+        }
+    }
+}
+
+fn write_level(queue: &wgpu::Queue, texture: &wgpu::Texture, mip_level: u32, rgba: &image::RgbaImage) {
+    write_layer(queue, texture, mip_level, 0, rgba);
+}
+
+fn write_layer(queue: &wgpu::Queue, texture: &wgpu::Texture, mip_level: u32, layer: u32, rgba: &image::RgbaImage) {
+    let (width, height) = rgba.dimensions();
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+            aspect: wgpu::TextureAspect::All,
+        },
+        rgba,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * width),
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Multiplies colour by alpha in place, for drawing with BlendMode::Premultiplied.
+/// sRGB pixels are scaled in linear space since that is where the blending happens.
+pub fn premultiply_alpha(rgba: &mut image::RgbaImage, color_space: ColorSpace) {
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+        if alpha == 1.0 {
+            continue;
+        }
+
+        for channel in &mut pixel.0[..3] {
+            let value = *channel as f32 / 255.0;
+            let scaled = match color_space {
+                ColorSpace::Srgb => linear_to_srgb(srgb_to_linear(value) * alpha),
+                ColorSpace::Linear => value * alpha,
+            };
+            *channel = (scaled * 255.0).round() as u8;
+        }
+    }
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mipmaps {
+    None,
+    /// Downsampled with a triangle filter before upload
+    Cpu,
+    /// Rendered level by level from the uploaded image
+    Gpu,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerPreset {
+    /// Nearest everything, for pixel art and the text sheet
+    Pixel,
+    /// Linear within a level, nearest between levels
+    Bilinear,
+    /// Linear within and between levels, what zoomed out mipmapped sprites want
+    Trilinear,
+}
+
+impl SamplerPreset {
+    /// (mag and min filter, mipmap filter)
+    pub fn filters(self) -> (wgpu::FilterMode, wgpu::FilterMode) {
+        match self {
+            SamplerPreset::Pixel => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest),
+            SamplerPreset::Bilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest),
+            SamplerPreset::Trilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colour images, decoded to linear when sampled
+    Srgb,
+    /// Data textures such as normal maps and lookup tables, sampled as stored
+    Linear,
+}
+
+/*
+How an image becomes a texture. Defaults match what from_image always did:
+sRGB, clamped, nearest filtering and no mips.
+
+Can also be read from a sidecar file next to the image (see resources::load_texture_options),
+e.g. background.png.meta:
+
+    # tiling background
+    address_mode = repeat
+    sampler = trilinear
+    mipmaps = gpu
+    premultiply_alpha = true
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureOptions {
+    pub color_space: ColorSpace,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub mipmaps: Mipmaps,
+    /// Multiply colour by alpha on import, see blend::BlendMode::Premultiplied
+    pub premultiply_alpha: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            color_space: ColorSpace::Srgb,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmaps: Mipmaps::None,
+            premultiply_alpha: false,
+        }
+    }
+}
+
+impl TextureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn linear(self) -> Self {
+        self.color_space(ColorSpace::Linear)
+    }
+
+    pub fn address_mode(self, mode: wgpu::AddressMode) -> Self {
+        self.address_modes(mode, mode)
+    }
+
+    pub fn address_modes(mut self, u: wgpu::AddressMode, v: wgpu::AddressMode) -> Self {
+        self.address_mode_u = u;
+        self.address_mode_v = v;
+        self
+    }
+
+    pub fn address_mode_u(mut self, mode: wgpu::AddressMode) -> Self {
+        self.address_mode_u = mode;
+        self
+    }
+
+    pub fn address_mode_v(mut self, mode: wgpu::AddressMode) -> Self {
+        self.address_mode_v = mode;
+        self
+    }
+
+    pub fn filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.mag_filter = filter;
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn mag_filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn min_filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn mipmap_filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.mipmap_filter = filter;
+        self
+    }
+
+    pub fn sampler(self, preset: SamplerPreset) -> Self {
+        let (filter, mipmap_filter) = preset.filters();
+        self.filter(filter).mipmap_filter(mipmap_filter)
+    }
+
+    pub fn mipmaps(mut self, mipmaps: Mipmaps) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    pub fn premultiply_alpha(mut self, premultiply: bool) -> Self {
+        self.premultiply_alpha = premultiply;
+        self
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self.color_space {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+
+    pub fn sampler_descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            ..Default::default()
+        }
+    }
+
+    /// Parses the `key = value` sidecar format, later lines override earlier ones.
+    pub fn from_sidecar(text: &str) -> Result<Self> {
+        let mut options = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected key = value", number + 1))?;
+            let (key, value) = (key.trim(), value.trim().to_ascii_lowercase());
+
+            options = match key {
+                "color_space" => options.color_space(match value.as_str() {
+                    "srgb" => ColorSpace::Srgb,
+                    "linear" => ColorSpace::Linear,
+                    _ => bail!("line {}: unknown color space {}", number + 1, value),
+                }),
+                "address_mode" => options.address_mode(parse_address_mode(&value, number)?),
+                "address_mode_u" => options.address_mode_u(parse_address_mode(&value, number)?),
+                "address_mode_v" => options.address_mode_v(parse_address_mode(&value, number)?),
+                "filter" => options.filter(parse_filter(&value, number)?),
+                "mag_filter" => options.mag_filter(parse_filter(&value, number)?),
+                "min_filter" => options.min_filter(parse_filter(&value, number)?),
+                "mipmap_filter" => options.mipmap_filter(parse_filter(&value, number)?),
+                "sampler" => options.sampler(match value.as_str() {
+                    "pixel" => SamplerPreset::Pixel,
+                    "bilinear" => SamplerPreset::Bilinear,
+                    "trilinear" => SamplerPreset::Trilinear,
+                    _ => bail!("line {}: unknown sampler preset {}", number + 1, value),
+                }),
+                "mipmaps" => options.mipmaps(match value.as_str() {
+                    "none" => Mipmaps::None,
+                    "cpu" => Mipmaps::Cpu,
+                    "gpu" => Mipmaps::Gpu,
+                    _ => bail!("line {}: unknown mipmap mode {}", number + 1, value),
+                }),
+                "premultiply_alpha" => options.premultiply_alpha(match value.as_str() {
+                    "true" | "yes" => true,
+                    "false" | "no" => false,
+                    _ => bail!("line {}: expected true or false, got {}", number + 1, value),
+                }),
+                _ => bail!("line {}: unknown option {}", number + 1, key),
+            };
+        }
+
+        Ok(options)
     }
-}
\ No newline at end of file
+}
+
+fn parse_address_mode(value: &str, number: usize) -> Result<wgpu::AddressMode> {
+    Ok(match value {
+        "clamp" => wgpu::AddressMode::ClampToEdge,
+        "repeat" => wgpu::AddressMode::Repeat,
+        "mirror" => wgpu::AddressMode::MirrorRepeat,
+        _ => bail!("line {}: unknown address mode {}", number + 1, value),
+    })
+}
+
+fn parse_filter(value: &str, number: usize) -> Result<wgpu::FilterMode> {
+    Ok(match value {
+        "nearest" => wgpu::FilterMode::Nearest,
+        "linear" => wgpu::FilterMode::Linear,
+        _ => bail!("line {}: unknown filter {}", number + 1, value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecar_keys() {
+        let options = TextureOptions::from_sidecar("
+            color_space = linear
+            address_mode_u = repeat
+            address_mode_v = Mirror
+            mag_filter = linear
+            min_filter = nearest
+            mipmap_filter = linear
+            mipmaps = gpu
+            premultiply_alpha = yes
+        ").unwrap();
+
+        let expected = TextureOptions::new()
+            .linear()
+            .address_modes(wgpu::AddressMode::Repeat, wgpu::AddressMode::MirrorRepeat)
+            .mag_filter(wgpu::FilterMode::Linear)
+            .min_filter(wgpu::FilterMode::Nearest)
+            .mipmap_filter(wgpu::FilterMode::Linear)
+            .mipmaps(Mipmaps::Gpu)
+            .premultiply_alpha(true);
+        assert_eq!(options, expected);
+    }
+
+    #[test]
+    fn sidecar_presets_and_later_lines_win() {
+        let options = TextureOptions::from_sidecar("
+            address_mode = repeat
+            address_mode_v = clamp
+            sampler = trilinear
+            filter = nearest
+        ").unwrap();
+
+        let expected = TextureOptions::new()
+            .address_modes(wgpu::AddressMode::Repeat, wgpu::AddressMode::ClampToEdge)
+            .sampler(SamplerPreset::Trilinear)
+            .filter(wgpu::FilterMode::Nearest);
+        assert_eq!(options, expected);
+    }
+
+    #[test]
+    fn sidecar_comments() {
+        let options = TextureOptions::from_sidecar("# whole line\n\n   \nfilter = linear # trailing\n#sampler = pixel\n").unwrap();
+
+        assert_eq!(options, TextureOptions::new().filter(wgpu::FilterMode::Linear));
+        assert_eq!(TextureOptions::from_sidecar("").unwrap(), TextureOptions::default());
+    }
+
+    #[test]
+    fn sidecar_error_lines() {
+        let error = |text: &str| TextureOptions::from_sidecar(text).unwrap_err().to_string();
+
+        assert_eq!(error("# comment\nfilter = linear\nfilter linear"), "line 3: expected key = value");
+        assert_eq!(error("\n\nfilter = cubic"), "line 3: unknown filter cubic");
+        assert_eq!(error("address_mode_u = wrap"), "line 1: unknown address mode wrap");
+        assert_eq!(error("mipmaps = none\nbrightness = 2"), "line 2: unknown option brightness");
+        assert_eq!(error("premultiply_alpha = maybe"), "line 1: expected true or false, got maybe");
+    }
+    #[test]
+    fn pixel_rect_bounds() {
+        let rect = |x, y, width, height| PixelRect { x, y, width, height };
+
+        assert!(rect(0, 0, 16, 8).fits_in(16, 8));
+        assert!(rect(15, 7, 1, 1).fits_in(16, 8));
+        assert!(!rect(15, 0, 2, 1).fits_in(16, 8));
+        assert!(!rect(0, 0, 0, 1).fits_in(16, 8));
+        // would wrap around to inside the texture without the overflow check
+        assert!(!rect(u32::MAX, 0, 2, 1).fits_in(16, 8));
+        assert!(!rect(0, 1, 1, u32::MAX).fits_in(16, 8));
+    }
+}
@@ -0,0 +1,762 @@
+/*
+Retained ui widgets as entities: a Widget component for where it is, and one of
+Label, Button, Checkbox, Slider, ProgressBar, ScrollView or Image for what it is.
+
+    let button = world.spawn((
+        widget::Widget::new([20.0, 300.0], [120.0, 28.0]),
+        widget::Button::new("Start"),
+        widget::OnEvent::new(|event| log::debug!("{:?}", event)),
+    ));
+    state.widgets.changed = true;
+
+Widgets are in screen space, positions in pixels from the bottom left corner of
+the window with y up. A widget given a parent is placed relative to the
+parent's top left corner instead, and inside a scroll view it moves with the
+scroll offset and is cut off at the edges of the view.
+
+Widgets::update gives every widget a WidgetState, keeps hover, press and focus
+up to date from the mouse and keyboard, and turns clicks, toggles, drags and
+scrolling into Events. Events go to the OnEvent component of their widget if it
+has one and can be read back from Widgets::events until the next update.
+Tab moves focus, space or return activates and the arrow keys move sliders.
+*/
+
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use winit::event::MouseButton;
+
+use crate::debug_draw;
+use crate::input_general::{Input, Key};
+use crate::shape;
+use crate::sprite;
+use crate::texture;
+
+pub type Color = [f32; 4];
+
+/// In front of the scene and the debug shapes, behind the overlays at 0.9.
+const Z: f32 = 0.85;
+/// Pixels scrolled per line of the mouse wheel.
+const SCROLL_STEP: f32 = 24.0;
+/// Parts of a slider's range the arrow keys move it by.
+const SLIDER_STEPS: f32 = 20.0;
+/// Deeper than any real layout, stops parent loops from recursing forever.
+const MAX_DEPTH: u32 = 64;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Widget {
+    /// Top left corner, from the bottom left of the window or the parent's top left.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub parent: Option<Entity>,
+    /// Hidden widgets hide their children too and can't be interacted with.
+    pub visible: bool,
+}
+
+impl Widget {
+    pub fn new(position: [f32; 2], size: [f32; 2]) -> Self {
+        Self { position, size, parent: None, visible: true }
+    }
+
+    pub fn child_of(mut self, parent: Entity) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+}
+
+/// Kept up to date by Widgets::update, pressed stays set while a press that began on the widget is held.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WidgetState {
+    pub hovered: bool,
+    pub pressed: bool,
+    pub focused: bool,
+}
+
+/// Text on its own, left aligned and centred vertically.
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub text: String,
+    /// The theme's when None.
+    pub size: Option<f32>,
+    pub color: Option<Color>,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), size: None, color: None }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Button {
+    pub text: String,
+}
+
+impl Button {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string() }
+    }
+}
+
+/// A box as tall as the widget with the text to its right.
+#[derive(Clone, Debug)]
+pub struct Checkbox {
+    pub text: String,
+    pub checked: bool,
+}
+
+impl Checkbox {
+    pub fn new(text: &str, checked: bool) -> Self {
+        Self { text: text.to_string(), checked }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Slider {
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Slider {
+    pub fn new(min: f32, max: f32, value: f32) -> Self {
+        Self { value: value.clamp(min, max), min, max }
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.max > self.min { (self.value - self.min) / (self.max - self.min) } else { 0.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ProgressBar {
+    /// 0 to 1.
+    pub value: f32,
+}
+
+impl ProgressBar {
+    pub fn new(value: f32) -> Self {
+        Self { value }
+    }
+}
+
+/// Shows content_height worth of children, offset pixels down from the top of it.
+#[derive(Copy, Clone, Debug)]
+pub struct ScrollView {
+    pub offset: f32,
+    pub content_height: f32,
+}
+
+impl ScrollView {
+    pub fn new(content_height: f32) -> Self {
+        Self { offset: 0.0, content_height }
+    }
+}
+
+/// A region of one layer of the sprite array, stretched over the widget.
+#[derive(Copy, Clone, Debug)]
+pub struct Image {
+    pub uv: texture::UvRect,
+    pub layer: u32,
+}
+
+impl Image {
+    pub fn new(uv: texture::UvRect, layer: u32) -> Self {
+        Self { uv, layer }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A button was pressed and released.
+    Clicked(Entity),
+    Toggled(Entity, bool),
+    /// A slider's new value.
+    Changed(Entity, f32),
+    /// A scroll view's new offset.
+    Scrolled(Entity, f32),
+    Focused(Entity),
+    Unfocused(Entity),
+}
+
+impl Event {
+    pub fn entity(&self) -> Entity {
+        match *self {
+            Event::Clicked(entity)
+            | Event::Toggled(entity, _)
+            | Event::Changed(entity, _)
+            | Event::Scrolled(entity, _)
+            | Event::Focused(entity)
+            | Event::Unfocused(entity) => entity,
+        }
+    }
+}
+
+/// Called with every event of the widget it is on.
+pub struct OnEvent(pub Box<dyn FnMut(&Event) + Send + Sync>);
+
+impl OnEvent {
+    pub fn new(callback: impl FnMut(&Event) + Send + Sync + 'static) -> Self {
+        Self(Box::new(callback))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Theme {
+    pub background: Color,
+    pub hovered: Color,
+    pub pressed: Color,
+    /// Behind scroll views, progress bars and slider tracks.
+    pub panel: Color,
+    pub accent: Color,
+    pub focus: Color,
+    pub text: Color,
+    pub text_size: f32,
+    pub corner_radius: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: [0.22, 0.24, 0.3, 1.0],
+            hovered: [0.3, 0.33, 0.41, 1.0],
+            pressed: [0.16, 0.17, 0.22, 1.0],
+            panel: [0.1, 0.11, 0.14, 0.9],
+            accent: [0.35, 0.6, 0.95, 1.0],
+            focus: [0.95, 0.8, 0.3, 1.0],
+            text: [0.95, 0.95, 0.95, 1.0],
+            text_size: 12.0,
+            corner_radius: 4.0,
+        }
+    }
+}
+
+impl Theme {
+    fn fill(&self, state: WidgetState) -> Color {
+        if state.pressed {
+            self.pressed
+        } else if state.hovered {
+            self.hovered
+        } else {
+            self.background
+        }
+    }
+}
+
+/// Everything drawn inside one clip rectangle, the window or a scroll view.
+#[derive(Default)]
+pub struct Group {
+    /// min x, min y, max x, max y in screen space, None for the whole window.
+    pub clip: Option<[f32; 4]>,
+    pub shapes: shape::ShapeMesh,
+    pub images: Vec<sprite::SpriteQuad>,
+    /// In the ui font, coloured.
+    pub text: debug_draw::DebugVecs,
+}
+
+impl Group {
+    fn new(clip: Option<[f32; 4]>) -> Self {
+        Self { clip, shapes: shape::ShapeMesh::new(Z), ..Self::default() }
+    }
+
+    fn rect(&mut self, min: [f32; 2], max: [f32; 2], radius: f32, color: Color) {
+        if max[0] - min[0] < 0.5 || max[1] - min[1] < 0.5 {
+            return;
+        }
+        let radius = radius.min((max[0] - min[0]) / 2.0).min((max[1] - min[1]) / 2.0);
+        if let Err(e) = self.shapes.fill(&shape::rounded_rect(min, max, radius), color) {
+            eprintln!("couldn't tessellate widget: {}", e);
+        }
+    }
+
+    fn outline(&mut self, min: [f32; 2], max: [f32; 2], radius: f32, width: f32, color: Color) {
+        let radius = radius.min((max[0] - min[0]) / 2.0).min((max[1] - min[1]) / 2.0).max(0.0);
+        if let Err(e) = self.shapes.stroke(&shape::rounded_rect(min, max, radius), &shape::Stroke::new(width), color) {
+            eprintln!("couldn't tessellate widget: {}", e);
+        }
+    }
+
+    /// Text starting at x, centred on y and cut short at max_x.
+    fn text(&mut self, x: f32, y: f32, max_x: f32, text: &str, size: f32, color: Color) {
+        let columns = ((max_x - x) / size).max(0.0) as usize;
+        let text = text.chars().take(columns).collect::<String>();
+        self.text.text([x, y + size / 2.0, Z], &text, size, color);
+    }
+
+    fn centred_text(&mut self, min: [f32; 2], max: [f32; 2], text: &str, size: f32, color: Color) {
+        let columns = ((max[0] - min[0]) / size).max(0.0) as usize;
+        let text = text.chars().take(columns).collect::<String>();
+        let width = text.chars().count() as f32 * size;
+        let x = (min[0] + max[0] - width) / 2.0;
+        self.text.text([x, (min[1] + max[1] + size) / 2.0, Z], &text, size, color);
+    }
+}
+
+/// A visible widget where it ends up on screen.
+#[derive(Copy, Clone, Debug)]
+struct Placed {
+    entity: Entity,
+    min: [f32; 2],
+    max: [f32; 2],
+    // what it is cut off by, None for nothing
+    clip: Option<[f32; 4]>,
+    // the innermost scroll view it is in
+    scroll: Option<Entity>,
+    depth: u32,
+}
+
+impl Placed {
+    fn contains(&self, point: [f32; 2]) -> bool {
+        let inside = |min: [f32; 2], max: [f32; 2]| {
+            point[0] >= min[0] && point[0] < max[0] && point[1] >= min[1] && point[1] < max[1]
+        };
+
+        inside(self.min, self.max) && self.clip.is_none_or(|clip| inside([clip[0], clip[1]], [clip[2], clip[3]]))
+    }
+
+    fn center_y(&self) -> f32 {
+        (self.min[1] + self.max[1]) / 2.0
+    }
+}
+
+#[derive(Default)]
+pub struct Widgets {
+    pub theme: Theme,
+    /// Widgets need drawing again, set by update and by whatever changes a widget or the theme.
+    pub changed: bool,
+    focused: Option<Entity>,
+    pressed: Option<Entity>,
+    events: Vec<Event>,
+}
+
+impl Widgets {
+    /// This frame's events, in the order they happened.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn focused(&self) -> Option<Entity> {
+        self.focused
+    }
+
+    pub fn update(&mut self, world: &mut World, input: &Input, window_height: f32) {
+        let mut events = Vec::new();
+
+        let new = world.query::<&Widget>().without::<&WidgetState>().iter().map(|(entity, _)| entity).collect::<Vec<_>>();
+        for entity in new {
+            let _ = world.insert_one(entity, WidgetState::default());
+            self.changed = true;
+        }
+
+        // despawned or hidden since
+        let placed = place(world);
+        let shown = |entity: Option<Entity>| entity.filter(|entity| placed.iter().any(|p| p.entity == *entity));
+        self.pressed = shown(self.pressed);
+        if self.focused.is_some() && shown(self.focused).is_none() {
+            self.focus(world, None, &mut events);
+        }
+
+        let mouse = input.mouse_pos();
+        let point = [mouse.x as f32, window_height - mouse.y as f32];
+        let hovered = placed.iter()
+            .filter(|p| is_interactive(world, p.entity) && p.contains(point))
+            .max_by_key(|p| p.depth)
+            .map(|p| p.entity);
+
+        if input.is_mouse_pressed(MouseButton::Left) {
+            self.pressed = hovered;
+            let focus = hovered.filter(|entity| is_focusable(world, *entity));
+            self.focus(world, focus, &mut events);
+        }
+        if input.is_mouse_down(MouseButton::Left) {
+            // dragging a slider's knob
+            if let Some(p) = self.pressed.and_then(|entity| placed.iter().find(|p| p.entity == entity)) {
+                let value = world.get::<&Slider>(p.entity).ok().map(|slider| {
+                    let radius = (p.max[1] - p.min[1]) / 2.0;
+                    let track = (p.max[0] - p.min[0] - radius * 2.0).max(1.0);
+                    let fraction = ((point[0] - p.min[0] - radius) / track).clamp(0.0, 1.0);
+                    slider.min + fraction * (slider.max - slider.min)
+                });
+                if let Some(value) = value {
+                    set_slider(world, p.entity, value, &mut events);
+                }
+            }
+        }
+        if input.is_mouse_released(MouseButton::Left) {
+            if let Some(entity) = self.pressed.take() {
+                if hovered == Some(entity) {
+                    activate(world, entity, &mut events);
+                }
+            }
+        }
+
+        // the wheel scrolls the innermost scroll view under the mouse
+        let scroll = input.scroll_delta();
+        if scroll != 0.0 {
+            let view = placed.iter()
+                .filter(|p| world.get::<&ScrollView>(p.entity).is_ok() && p.contains(point))
+                .max_by_key(|p| p.depth);
+            if let Some(p) = view {
+                let mut view = world.get::<&mut ScrollView>(p.entity).unwrap();
+                let limit = (view.content_height - (p.max[1] - p.min[1])).max(0.0);
+                let offset = (view.offset - scroll * SCROLL_STEP).clamp(0.0, limit);
+                if offset != view.offset {
+                    view.offset = offset;
+                    events.push(Event::Scrolled(p.entity, offset));
+                }
+            }
+        }
+
+        if input.is_key_pressed(Key::Tab) {
+            let focusable = placed.iter().map(|p| p.entity).filter(|entity| is_focusable(world, *entity)).collect::<Vec<_>>();
+            let next = match self.focused.and_then(|focused| focusable.iter().position(|entity| *entity == focused)) {
+                Some(i) => focusable.get((i + 1) % focusable.len()),
+                None => focusable.first(),
+            };
+            self.focus(world, next.copied(), &mut events);
+        }
+        if let Some(focused) = self.focused {
+            if input.is_key_pressed(Key::Space) || input.is_key_pressed(Key::Return) {
+                activate(world, focused, &mut events);
+            }
+
+            let steps = input.is_key_pressed(Key::Right) as i32 - input.is_key_pressed(Key::Left) as i32;
+            let value = world.get::<&Slider>(focused).ok().map(|slider| slider.value + steps as f32 * (slider.max - slider.min) / SLIDER_STEPS);
+            if let (Some(value), true) = (value, steps != 0) {
+                set_slider(world, focused, value, &mut events);
+            }
+        }
+
+        for (entity, state) in world.query_mut::<&mut WidgetState>() {
+            let new = WidgetState {
+                hovered: hovered == Some(entity),
+                pressed: self.pressed == Some(entity),
+                focused: self.focused == Some(entity),
+            };
+            if *state != new {
+                *state = new;
+                self.changed = true;
+            }
+        }
+
+        for event in &events {
+            if let Ok(mut on_event) = world.get::<&mut OnEvent>(event.entity()) {
+                (on_event.0)(event);
+            }
+        }
+        if !events.is_empty() {
+            self.changed = true;
+        }
+        self.events = events;
+    }
+
+    fn focus(&mut self, world: &World, focus: Option<Entity>, events: &mut Vec<Event>) {
+        if focus == self.focused {
+            return;
+        }
+
+        if let Some(old) = self.focused.filter(|old| world.contains(*old)) {
+            events.push(Event::Unfocused(old));
+        }
+        if let Some(new) = focus {
+            events.push(Event::Focused(new));
+        }
+        self.focused = focus;
+    }
+
+    /// Every visible widget as shapes, images and text, one group per clip rectangle.
+    pub fn draw(&self, world: &World) -> Vec<Group> {
+        let theme = &self.theme;
+        let mut groups = vec![Group::new(None)];
+        let mut group_of = HashMap::from([(None, 0)]);
+
+        // parents come first, so scroll views are drawn before what is in them
+        for p in place(world) {
+            let group = *group_of.entry(p.scroll).or_insert_with(|| {
+                groups.push(Group::new(p.clip));
+                groups.len() - 1
+            });
+            let group = &mut groups[group];
+
+            let entity = p.entity;
+            let (min, max) = (p.min, p.max);
+            let height = max[1] - min[1];
+            let state = world.get::<&WidgetState>(entity).map(|state| *state).unwrap_or_default();
+
+            if let Ok(view) = world.get::<&ScrollView>(entity) {
+                group.rect(min, max, theme.corner_radius, theme.panel);
+
+                if view.content_height > height {
+                    let thumb = height * height / view.content_height;
+                    let top = max[1] - view.offset / view.content_height * height;
+                    group.rect([max[0] - 6.0, top - thumb], [max[0] - 2.0, top], 2.0, theme.hovered);
+                }
+            }
+            if let Ok(image) = world.get::<&Image>(entity) {
+                group.images.push(sprite::SpriteQuad {
+                    position: [min[0], max[1], Z],
+                    size: [max[0] - min[0], height],
+                    uv: image.uv,
+                    layer: image.layer,
+                });
+            }
+            if let Ok(bar) = world.get::<&ProgressBar>(entity) {
+                let filled = min[0] + (max[0] - min[0]) * bar.value.clamp(0.0, 1.0);
+                group.rect(min, max, theme.corner_radius, theme.panel);
+                group.rect(min, [filled, max[1]], theme.corner_radius, theme.accent);
+            }
+            if let Ok(label) = world.get::<&Label>(entity) {
+                let size = label.size.unwrap_or(theme.text_size);
+                group.text(min[0], p.center_y(), max[0], &label.text, size, label.color.unwrap_or(theme.text));
+            }
+            if let Ok(button) = world.get::<&Button>(entity) {
+                group.rect(min, max, theme.corner_radius, theme.fill(state));
+                group.centred_text(min, max, &button.text, theme.text_size, theme.text);
+            }
+            if let Ok(checkbox) = world.get::<&Checkbox>(entity) {
+                let box_max = [min[0] + height, max[1]];
+                group.rect(min, box_max, theme.corner_radius, theme.fill(state));
+                if checkbox.checked {
+                    let at = |x: f32, y: f32| [min[0] + x * height, min[1] + y * height];
+                    let tick = shape::polyline(&[at(0.25, 0.5), at(0.42, 0.3), at(0.75, 0.72)]);
+                    let stroke = shape::Stroke::new(height * 0.12).join(shape::LineJoin::Round).cap(shape::LineCap::Round);
+                    if let Err(e) = group.shapes.stroke(&tick, &stroke, theme.accent) {
+                        eprintln!("couldn't tessellate widget: {}", e);
+                    }
+                }
+                group.text(box_max[0] + theme.text_size / 2.0, p.center_y(), max[0], &checkbox.text, theme.text_size, theme.text);
+            }
+            if let Ok(slider) = world.get::<&Slider>(entity) {
+                let radius = height / 2.0;
+                let knob = min[0] + radius + slider.fraction() * (max[0] - min[0] - radius * 2.0);
+                let y = p.center_y();
+                group.rect([min[0] + radius, y - 2.0], [max[0] - radius, y + 2.0], 2.0, theme.panel);
+                group.rect([min[0] + radius, y - 2.0], [knob, y + 2.0], 2.0, theme.accent);
+                group.rect([knob - radius * 0.8, y - radius * 0.8], [knob + radius * 0.8, y + radius * 0.8], radius, theme.fill(state));
+            }
+
+            if state.focused {
+                group.outline(min, max, theme.corner_radius, 2.0, theme.focus);
+            }
+        }
+
+        groups
+    }
+}
+
+fn is_interactive(world: &World, entity: Entity) -> bool {
+    is_focusable(world, entity) || world.get::<&ScrollView>(entity).is_ok()
+}
+
+fn is_focusable(world: &World, entity: Entity) -> bool {
+    world.get::<&Button>(entity).is_ok() || world.get::<&Checkbox>(entity).is_ok() || world.get::<&Slider>(entity).is_ok()
+}
+
+/// What clicking or pressing space on a widget does.
+fn activate(world: &World, entity: Entity, events: &mut Vec<Event>) {
+    if world.get::<&Button>(entity).is_ok() {
+        events.push(Event::Clicked(entity));
+    }
+    if let Ok(mut checkbox) = world.get::<&mut Checkbox>(entity) {
+        checkbox.checked = !checkbox.checked;
+        events.push(Event::Toggled(entity, checkbox.checked));
+    }
+}
+
+fn set_slider(world: &World, entity: Entity, value: f32, events: &mut Vec<Event>) {
+    if let Ok(mut slider) = world.get::<&mut Slider>(entity) {
+        let value = value.clamp(slider.min.min(slider.max), slider.max.max(slider.min));
+        if value != slider.value {
+            slider.value = value;
+            events.push(Event::Changed(entity, value));
+        }
+    }
+}
+
+/// Every visible widget, parents before their children.
+fn place(world: &World) -> Vec<Placed> {
+    let widgets = world.query::<&Widget>().iter().map(|(entity, widget)| (entity, *widget)).collect::<Vec<_>>();
+    let by_entity = widgets.iter().copied().collect::<HashMap<_, _>>();
+
+    let mut placed = widgets.iter()
+        .filter_map(|(entity, _)| resolve(world, &by_entity, *entity, 0))
+        .collect::<Vec<_>>();
+    placed.sort_by_key(|p| p.depth);
+
+    placed
+}
+
+fn resolve(world: &World, widgets: &HashMap<Entity, Widget>, entity: Entity, depth: u32) -> Option<Placed> {
+    let widget = widgets.get(&entity)?;
+    if !widget.visible || depth > MAX_DEPTH {
+        return None;
+    }
+
+    let (origin, clip, scroll, depth) = match widget.parent {
+        None => ([0.0, 0.0], None, None, 0),
+        Some(parent) => {
+            let parent = resolve(world, widgets, parent, depth + 1)?;
+            let top_left = [parent.min[0], parent.max[1]];
+            match world.get::<&ScrollView>(parent.entity) {
+                Ok(view) => {
+                    let rect = [parent.min[0], parent.min[1], parent.max[0], parent.max[1]];
+                    let clip = match parent.clip {
+                        Some(outer) => [rect[0].max(outer[0]), rect[1].max(outer[1]), rect[2].min(outer[2]), rect[3].min(outer[3])],
+                        None => rect,
+                    };
+                    ([top_left[0], top_left[1] + view.offset], Some(clip), Some(parent.entity), parent.depth + 1)
+                },
+                Err(_) => (top_left, parent.clip, parent.scroll, parent.depth + 1),
+            }
+        },
+    };
+
+    let top_left = [origin[0] + widget.position[0], origin[1] + widget.position[1]];
+    Some(Placed {
+        entity,
+        min: [top_left[0], top_left[1] - widget.size[1]],
+        max: [top_left[0] + widget.size[0], top_left[1]],
+        clip,
+        scroll,
+        depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{ElementState, KeyboardInput};
+
+    const HEIGHT: f32 = 600.0;
+
+    #[allow(deprecated)]
+    fn key(key: Key, state: ElementState) -> KeyboardInput {
+        KeyboardInput { scancode: 0, state, virtual_keycode: Some(key), modifiers: Default::default() }
+    }
+
+    /// Presses and lets go of a key with an update in between, returns that update's events.
+    fn tap(widgets: &mut Widgets, world: &mut World, input: &mut Input, pressed: Key) -> Vec<Event> {
+        input.handle_key_event(key(pressed, ElementState::Pressed));
+        widgets.update(world, input, HEIGHT);
+        input.next_frame();
+        input.handle_key_event(key(pressed, ElementState::Released));
+        input.next_frame();
+        widgets.events().to_vec()
+    }
+
+    #[test]
+    fn tab_cycles_through_focusable_widgets() {
+        let mut world = World::new();
+        let buttons = (0..3)
+            .map(|i| world.spawn((Widget::new([20.0, 500.0 - i as f32 * 40.0], [120.0, 28.0]), Button::new("button"))))
+            .collect::<Vec<_>>();
+        world.spawn((Widget::new([20.0, 300.0], [120.0, 28.0]), Label::new("label")));
+        let mut hidden = Widget::new([20.0, 260.0], [120.0, 28.0]);
+        hidden.visible = false;
+        world.spawn((hidden, Button::new("hidden")));
+
+        let (mut widgets, mut input) = (Widgets::default(), Input::default());
+        widgets.update(&mut world, &input, HEIGHT);
+        assert_eq!(widgets.focused(), None);
+
+        let first = tap(&mut widgets, &mut world, &mut input, Key::Tab);
+        let mut visited = vec![widgets.focused().unwrap()];
+        assert_eq!(first, [Event::Focused(visited[0])]);
+        for _ in 1..3 {
+            let events = tap(&mut widgets, &mut world, &mut input, Key::Tab);
+            let focused = widgets.focused().unwrap();
+            assert_eq!(events, [Event::Unfocused(*visited.last().unwrap()), Event::Focused(focused)]);
+            visited.push(focused);
+        }
+        visited.sort();
+        let mut expected = buttons.clone();
+        expected.sort();
+        assert_eq!(visited, expected);
+
+        // wraps around, and the state follows
+        tap(&mut widgets, &mut world, &mut input, Key::Tab);
+        let focused = widgets.focused().unwrap();
+        assert_eq!(buttons.iter().filter(|entity| world.get::<&WidgetState>(**entity).unwrap().focused).collect::<Vec<_>>(), [&focused]);
+
+        // a widget losing focus by being hidden
+        world.get::<&mut Widget>(focused).unwrap().visible = false;
+        widgets.update(&mut world, &input, HEIGHT);
+        assert_eq!(widgets.focused(), None);
+        assert_eq!(widgets.events(), [Event::Unfocused(focused)]);
+    }
+
+    #[test]
+    fn space_activates_the_focused_widget() {
+        let mut world = World::new();
+        let checkbox = world.spawn((Widget::new([20.0, 500.0], [120.0, 20.0]), Checkbox::new("checkbox", false)));
+        let (mut widgets, mut input) = (Widgets::default(), Input::default());
+
+        // nothing focused, nothing to activate
+        assert!(tap(&mut widgets, &mut world, &mut input, Key::Space).is_empty());
+
+        tap(&mut widgets, &mut world, &mut input, Key::Tab);
+        assert_eq!(widgets.focused(), Some(checkbox));
+        assert_eq!(tap(&mut widgets, &mut world, &mut input, Key::Space), [Event::Toggled(checkbox, true)]);
+        assert_eq!(tap(&mut widgets, &mut world, &mut input, Key::Return), [Event::Toggled(checkbox, false)]);
+        assert!(!world.get::<&Checkbox>(checkbox).unwrap().checked);
+
+        // and only on the frame it goes down
+        input.handle_key_event(key(Key::Space, ElementState::Pressed));
+        widgets.update(&mut world, &input, HEIGHT);
+        input.next_frame();
+        widgets.update(&mut world, &input, HEIGHT);
+        assert!(widgets.events().is_empty());
+        assert!(world.get::<&Checkbox>(checkbox).unwrap().checked);
+    }
+
+    #[test]
+    fn clicked_buttons_call_on_event() {
+        let mut world = World::new();
+        let clicks = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = clicks.clone();
+        let button = world.spawn((
+            Widget::new([20.0, 500.0], [120.0, 28.0]),
+            Button::new("button"),
+            OnEvent::new(move |event| if let Event::Clicked(_) = event {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }),
+        ));
+        let (mut widgets, mut input) = (Widgets::default(), Input::default());
+
+        tap(&mut widgets, &mut world, &mut input, Key::Tab);
+        assert_eq!(tap(&mut widgets, &mut world, &mut input, Key::Space), [Event::Clicked(button)]);
+        assert_eq!(clicks.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn sliders_step_and_clamp() {
+        let mut world = World::new();
+        assert_eq!(Slider::new(0.0, 10.0, 12.0).value, 10.0);
+        assert_eq!(Slider::new(0.0, 10.0, -1.0).value, 0.0);
+
+        let slider = world.spawn((Widget::new([20.0, 500.0], [200.0, 20.0]), Slider::new(0.0, 10.0, 9.0)));
+        let (mut widgets, mut input) = (Widgets::default(), Input::default());
+        let value = |world: &World| world.get::<&Slider>(slider).unwrap().value;
+
+        // unfocused sliders ignore the arrow keys
+        assert!(tap(&mut widgets, &mut world, &mut input, Key::Right).is_empty());
+        tap(&mut widgets, &mut world, &mut input, Key::Tab);
+
+        assert_eq!(tap(&mut widgets, &mut world, &mut input, Key::Right), [Event::Changed(slider, 9.5)]);
+        assert_eq!(tap(&mut widgets, &mut world, &mut input, Key::Right), [Event::Changed(slider, 10.0)]);
+        // already at the end, no change and no event
+        assert!(tap(&mut widgets, &mut world, &mut input, Key::Right).is_empty());
+        assert_eq!(value(&world), 10.0);
+
+        for _ in 0..25 {
+            tap(&mut widgets, &mut world, &mut input, Key::Left);
+        }
+        assert_eq!(value(&world), 0.0);
+
+        // dragging past the ends of the track clamps too
+        let mut events = Vec::new();
+        set_slider(&world, slider, 14.0, &mut events);
+        set_slider(&world, slider, -3.0, &mut events);
+        assert_eq!(events, [Event::Changed(slider, 10.0), Event::Changed(slider, 0.0)]);
+    }
+}